@@ -0,0 +1,253 @@
+//! Bootstrap estimates of how stable the per-bit error curves are.
+
+use anyhow::{ensure, Result};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::models::FitConfig;
+use crate::sweep::{Sweep, SweepErrors};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootstrapConfig {
+    /// Number of resampled distributions to fit.
+    pub resamples: usize,
+    /// Number of draws per resample, the number of pixels the distribution
+    /// was counted from. The normalized distribution does not record it.
+    pub samples: usize,
+    /// Fraction of the resampled errors inside of the percentile band.
+    pub confidence: f64,
+    pub seed: u64,
+    /// The bit depths every resample is fitted for.
+    pub sweep: Sweep,
+}
+
+impl BootstrapConfig {
+    /// Resamples a distribution of `samples` pixels.
+    pub fn new(samples: usize) -> Self {
+        Self {
+            resamples: 100,
+            samples,
+            confidence: 0.9,
+            seed: 0,
+            sweep: Sweep::default(),
+        }
+    }
+
+    pub fn with_resamples(mut self, resamples: usize) -> Self {
+        self.resamples = resamples;
+        self
+    }
+    pub fn with_confidence(mut self, confidence: f64) -> Self {
+        self.confidence = confidence;
+        self
+    }
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    pub fn with_sweep(mut self, sweep: Sweep) -> Self {
+        self.sweep = sweep;
+        self
+    }
+}
+
+/// Spread of the error for a single level count over all resamples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorBand {
+    pub mean: f64,
+    pub std_dev: f64,
+    /// Lower and upper percentile of the configured confidence.
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// `(levels, band)` pairs of a [`Sweep`], in order of increasing level count.
+pub type SweepBands = Vec<(u64, ErrorBand)>;
+
+/// Which value of an [`ErrorBand`] is used as a single error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BandStatistic {
+    Mean,
+    Lower,
+    Upper,
+    /// `mean + k * std_dev`
+    MeanPlusStdDev(f64),
+}
+
+impl ErrorBand {
+    pub fn from_errors(errors: &[f64], confidence: f64) -> Self {
+        let mut sorted = errors.to_vec();
+        sorted.sort_unstable_by(|a, b| a.total_cmp(b));
+        let n = sorted.len() as f64;
+        let mean = sorted.iter().sum::<f64>() / n;
+        let variance = sorted.iter().map(|e| (e - mean).powi(2)).sum::<f64>() / (n - 1.).max(1.);
+        let tail = (1. - confidence) / 2.;
+        Self {
+            mean,
+            std_dev: variance.sqrt(),
+            lower: percentile(&sorted, tail),
+            upper: percentile(&sorted, 1. - tail),
+        }
+    }
+
+    pub fn statistic(&self, statistic: BandStatistic) -> f64 {
+        match statistic {
+            BandStatistic::Mean => self.mean,
+            BandStatistic::Lower => self.lower,
+            BandStatistic::Upper => self.upper,
+            BandStatistic::MeanPlusStdDev(k) => self.mean + k * self.std_dev,
+        }
+    }
+}
+
+/// One value per level count, e.g. to feed
+/// [`crate::packing::DynamicErrorFunction::from_sweep`].
+pub fn curve(bands: &[(u64, ErrorBand)], statistic: BandStatistic) -> SweepErrors {
+    bands
+        .iter()
+        .map(|(levels, band)| (*levels, band.statistic(statistic)))
+        .collect()
+}
+
+/// Linearly interpolated percentile `p` in [0, 1] of sorted values.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let position = p.clamp(0., 1.) * (sorted.len() - 1) as f64;
+    let below = position.floor() as usize;
+    let above = position.ceil() as usize;
+    sorted[below] + (sorted[above] - sorted[below]) * (position - below as f64)
+}
+
+/// Draws `samples` points from the normalized distribution with replacement
+/// and returns the normalized distribution of the drawn points.
+pub fn resample(dist: &[(f64, f64)], samples: usize, rng: &mut impl Rng) -> Vec<(f64, f64)> {
+    let total = dist.last().map_or(0., |&(_, y)| y);
+    let mut counts = vec![0u64; dist.len()];
+    for _ in 0..samples {
+        let u = rng.gen::<f64>() * total;
+        let index = dist.partition_point(|&(_, y)| y <= u).min(dist.len() - 1);
+        counts[index] += 1;
+    }
+    let mut cumulative = 0;
+    dist.iter()
+        .zip(counts)
+        .filter(|&(_, count)| count > 0)
+        .map(|(&(x, _), count)| {
+            cumulative += count;
+            (x, cumulative as f64 / samples as f64)
+        })
+        .collect()
+}
+
+/// Refits `name` on resamples of `train` and summarizes the errors on `test`
+/// for every level count of the configured sweep.
+pub fn bootstrap_error_function(
+    train: &[(f64, f64)],
+    name: &str,
+    test: &[(f64, f64)],
+    config: &FitConfig,
+    bootstrap: &BootstrapConfig,
+) -> Result<SweepBands> {
+    ensure!(!train.is_empty(), "can not bootstrap an empty distribution");
+    ensure!(
+        bootstrap.resamples > 0,
+        "bootstrap needs at least one resample"
+    );
+    ensure!(bootstrap.samples > 0, "bootstrap needs at least one sample");
+    let mut rng = StdRng::seed_from_u64(bootstrap.seed);
+    let levels = &bootstrap.sweep.levels;
+    let mut errors = vec![Vec::new(); levels.len()];
+    for _ in 0..bootstrap.resamples {
+        let resampled = resample(train, bootstrap.samples, &mut rng);
+        let curve =
+            crate::calculate_error_function_with(&resampled, name, test, config, &bootstrap.sweep)?;
+        for (errors, (_, error)) in errors.iter_mut().zip(curve) {
+            errors.push(error);
+        }
+    }
+    Ok(levels
+        .iter()
+        .zip(&errors)
+        .map(|(&levels, errors)| (levels, ErrorBand::from_errors(errors, bootstrap.confidence)))
+        .collect())
+}
+
+/// [`bootstrap_error_function`] for every registered model.
+pub fn bootstrap_error_functions(
+    train: &[(f64, f64)],
+    test: &[(f64, f64)],
+    config: &FitConfig,
+    bootstrap: &BootstrapConfig,
+) -> Result<(Vec<String>, Vec<SweepBands>)> {
+    let names = crate::registry::names();
+    let bands = names
+        .iter()
+        .map(|name| bootstrap_error_function(train, name, test, config, bootstrap))
+        .collect::<Result<_>>()?;
+    Ok((names, bands))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bands_and_resampling() {
+        let band = ErrorBand::from_errors(&[4., 1., 3., 2., 5.], 0.5);
+        assert_eq!(band.mean, 3.);
+        assert_eq!((band.lower, band.upper), (2., 4.));
+        assert!((band.std_dev - 2.5f64.sqrt()).abs() < 1e-12);
+
+        let dist: Vec<_> = (1..=10).map(|i| (i as f64 / 10., i as f64 / 10.)).collect();
+        let resampled = resample(&dist, 1000, &mut StdRng::seed_from_u64(1));
+        assert_eq!(resampled.last().unwrap().1, 1.);
+        assert!(resampled
+            .windows(2)
+            .all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+        let median = resampled.iter().find(|&&(_, y)| y >= 0.5).unwrap().0;
+        assert!((0.4..=0.6).contains(&median), "{:?}", resampled);
+    }
+
+    #[test]
+    fn band_width() {
+        let dist: Vec<_> = (1..=20)
+            .map(|i| (i as f64 / 20., (i as f64 / 20.).powi(2)))
+            .collect();
+        let config = FitConfig::default();
+        let bands = |samples| {
+            let bootstrap = BootstrapConfig::new(samples)
+                .with_resamples(30)
+                .with_sweep(Sweep::new(2..4));
+            bootstrap_error_function(&dist, "lloyd-max", &dist, &config, &bootstrap).unwrap()
+        };
+        let exact = crate::calculate_error_function_with(
+            &dist,
+            "lloyd-max",
+            &dist,
+            &config,
+            &Sweep::new(2..4),
+        )
+        .unwrap();
+        let few = bands(50);
+        let many = bands(100_000);
+        for ((&(levels, few), &(_, many)), &(_, exact)) in few.iter().zip(&many).zip(&exact) {
+            assert!(few.lower <= few.mean && few.mean <= few.upper, "{:?}", few);
+            // the spread shrinks with the square root of the pixel count and
+            // the mean converges to the error of the fit on `dist` itself
+            assert!(
+                few.upper - few.lower > 5. * (many.upper - many.lower),
+                "{} levels: {:?} {:?}",
+                levels,
+                few,
+                many
+            );
+            assert!(
+                (many.mean - exact).abs() < 0.1 * exact,
+                "{:?} {}",
+                many,
+                exact
+            );
+        }
+
+        let empty = BootstrapConfig::new(0);
+        assert!(bootstrap_error_function(&dist, "empirical", &dist, &config, &empty).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! Train/test splits and k-fold cross-validation of the fitted models.
+//!
+//! Splits operate on the raw pixel values of one channel, so train and test
+//! distributions are normalized with the same scale.
+
+use anyhow::{ensure, Result};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::bootstrap::ErrorBand;
+use crate::models::FitConfig;
+use crate::registry::ModelDescriptor;
+use crate::sweep::Sweep;
+use crate::{registry, Dist, Normalization};
+
+/// How pixels are grouped before they are assigned to a split.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitStrategy {
+    /// Every pixel is assigned independently.
+    Random { seed: u64 },
+    /// Square blocks of `block` pixels in an image of `width` pixels per row
+    /// are assigned as a whole, so neighbouring pixels do not end up in both
+    /// sets.
+    Blocks {
+        width: usize,
+        block: usize,
+        seed: u64,
+    },
+}
+
+impl SplitStrategy {
+    /// The group of every pixel, shuffled so that consecutive group indices
+    /// are unrelated.
+    fn groups(&self, len: usize) -> Result<(Vec<usize>, usize)> {
+        let (seed, groups, count) = match *self {
+            SplitStrategy::Random { seed } => (seed, (0..len).collect(), len),
+            SplitStrategy::Blocks { width, block, seed } => {
+                ensure!(
+                    width > 0 && block > 0,
+                    "blocks need a positive image width and block size"
+                );
+                let columns = width.div_ceil(block);
+                let groups: Vec<_> = (0..len)
+                    .map(|i| (i / width / block) * columns + (i % width) / block)
+                    .collect();
+                let count = groups.iter().max().map_or(0, |&g| g + 1);
+                (seed, groups, count)
+            }
+        };
+        let mut order: Vec<usize> = (0..count).collect();
+        order.shuffle(&mut StdRng::seed_from_u64(seed));
+        Ok((groups.into_iter().map(|g| order[g]).collect(), count))
+    }
+
+    /// Assigns every pixel to one of `folds` folds of similar size.
+    pub fn folds(&self, len: usize, folds: usize) -> Result<Vec<usize>> {
+        ensure!(folds > 0, "can not assign pixels to zero folds");
+        let (groups, _) = self.groups(len)?;
+        Ok(groups.into_iter().map(|g| g % folds).collect())
+    }
+}
+
+/// The normalization of the distributions of all splits of `values`.
+pub fn normalization(values: &[f64]) -> Normalization {
+    Normalization::new(0., values.iter().cloned().fold(f64::MIN, f64::max))
+}
+
+/// Normalized distribution of `values` with `x` divided by `max`.
+pub fn distribution(values: &[f64], max: f64) -> Vec<(f64, f64)> {
+    let mut dist = crate::integrate_distribution(values.to_vec());
+    crate::drop_duplicates(&mut dist);
+    let total = values.len() as f64;
+    dist.into_iter()
+        .map(|(x, y)| (x / max, y / total))
+        .collect()
+}
+
+/// Normalized train and test distributions of the same channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Split {
+    pub train: Dist,
+    pub test: Dist,
+    /// The normalization applied to both distributions, to convert errors
+    /// back into raw units.
+    pub normalization: Normalization,
+}
+
+/// Splits `values` into train and test distributions, with roughly
+/// `test_fraction` of the pixel groups in the test set. Fails if either set
+/// ends up empty.
+pub fn train_test_split(
+    values: &[f64],
+    test_fraction: f64,
+    strategy: SplitStrategy,
+) -> Result<Split> {
+    let (groups, count) = strategy.groups(values.len())?;
+    let test_groups = (count as f64 * test_fraction).round() as usize;
+    let normalization = normalization(values);
+    let (test, train) = split(values, &groups, |group| group < test_groups);
+    ensure!(
+        !test.is_empty() && !train.is_empty(),
+        "a test fraction of {} leaves the train or the test set empty",
+        test_fraction
+    );
+    Ok(Split {
+        train: distribution(&train, normalization.scale),
+        test: distribution(&test, normalization.scale),
+        normalization,
+    })
+}
+
+/// Splits `values` by whether `predicate` holds for their label.
+fn split(
+    values: &[f64],
+    labels: &[usize],
+    predicate: impl Fn(usize) -> bool,
+) -> (Vec<f64>, Vec<f64>) {
+    let (matching, rest): (Vec<_>, Vec<_>) = values
+        .iter()
+        .zip(labels)
+        .partition(|&(_, &label)| predicate(label));
+    let values = |split: Vec<(&f64, &usize)>| split.into_iter().map(|(&v, _)| v).collect();
+    (values(matching), values(rest))
+}
+
+/// Errors of one model over all folds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelValidation {
+    pub name: String,
+    /// Level count of every depth of the sweep.
+    pub levels: Vec<u64>,
+    /// Error on the training folds for every level count.
+    pub train: Vec<ErrorBand>,
+    /// Error on the held out fold for every level count.
+    pub test: Vec<ErrorBand>,
+}
+
+impl ModelValidation {
+    /// Mean test error minus mean train error for every level count. Large
+    /// gaps indicate overfitting.
+    pub fn generalization_gap(&self) -> Vec<f64> {
+        self.train
+            .iter()
+            .zip(&self.test)
+            .map(|(train, test)| test.mean - train.mean)
+            .collect()
+    }
+}
+
+/// Fits every registered model on `folds - 1` folds and measures the error
+/// on the remaining one, for every level count of `sweep`. The bands span the
+/// minimum and maximum error over all folds.
+pub fn cross_validate(
+    values: &[f64],
+    folds: usize,
+    strategy: SplitStrategy,
+    config: &FitConfig,
+    sweep: &Sweep,
+) -> Result<Vec<ModelValidation>> {
+    cross_validate_models(
+        &registry::descriptors(),
+        values,
+        folds,
+        strategy,
+        config,
+        sweep,
+    )
+}
+
+/// [`cross_validate`] for the given models only.
+pub fn cross_validate_models(
+    models: &[ModelDescriptor],
+    values: &[f64],
+    folds: usize,
+    strategy: SplitStrategy,
+    config: &FitConfig,
+    sweep: &Sweep,
+) -> Result<Vec<ModelValidation>> {
+    ensure!(folds >= 2, "cross-validation needs at least two folds");
+    let assignment = strategy.folds(values.len(), folds)?;
+    let max = normalization(values).scale;
+    let splits: Vec<_> = (0..folds)
+        .map(|fold| {
+            let (test, train) = split(values, &assignment, |f| f == fold);
+            ensure!(
+                !test.is_empty() && !train.is_empty(),
+                "fold {} is empty, use fewer folds or smaller blocks",
+                fold
+            );
+            Ok((distribution(&train, max), distribution(&test, max)))
+        })
+        .collect::<Result<_>>()?;
+
+    models
+        .iter()
+        .map(|model| {
+            // errors[fold][depth] on the training and the held out data
+            let errors = splits
+                .iter()
+                .map(|(train, test)| {
+                    sweep.map(model, train, config, |levels, fit| {
+                        let error = |dist| model.error(dist, fit, levels, &config.error);
+                        (error(train), error(test))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let band = |depth: usize, select: fn(&(f64, f64)) -> f64| {
+                let errors: Vec<_> = errors.iter().map(|fold| select(&fold[depth])).collect();
+                ErrorBand::from_errors(&errors, 1.)
+            };
+            let depths = sweep.levels.len();
+            Ok(ModelValidation {
+                name: model.name.clone(),
+                levels: sweep.levels.clone(),
+                train: (0..depths).map(|d| band(d, |e| e.0)).collect(),
+                test: (0..depths).map(|d| band(d, |e| e.1)).collect(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metric::{ErrorOptions, Weighting};
+
+    #[test]
+    fn splits() {
+        let values: Vec<f64> = (0..64).map(|i| i as f64).collect();
+        let Split {
+            train,
+            test,
+            normalization,
+        } = train_test_split(&values, 0.25, SplitStrategy::Random { seed: 1 }).unwrap();
+        assert_eq!(normalization, Normalization::new(0., 63.));
+        assert_eq!(train.len() + test.len(), 64);
+        assert_eq!(test.len(), 16);
+        assert_eq!(test.last().unwrap().1, 1.);
+
+        let blocks = SplitStrategy::Blocks {
+            width: 8,
+            block: 4,
+            seed: 2,
+        };
+        let folds = blocks.folds(values.len(), 4).unwrap();
+        // every 4x4 block ends up in a single fold and every fold gets one
+        for (i, &fold) in folds.iter().enumerate() {
+            let corner = (i / 8 / 4) * 32 + (i % 8) / 4 * 4;
+            assert_eq!(fold, folds[corner]);
+        }
+        let mut used = folds.clone();
+        used.sort_unstable();
+        used.dedup();
+        assert_eq!(used, vec![0, 1, 2, 3]);
+
+        let random = SplitStrategy::Random { seed: 1 };
+        assert!(train_test_split(&values, 0., random).is_err());
+        assert!(train_test_split(&values, 1., random).is_err());
+        let no_width = SplitStrategy::Blocks {
+            width: 0,
+            block: 4,
+            seed: 2,
+        };
+        assert!(no_width.folds(values.len(), 4).is_err());
+        assert!(train_test_split(&values, 0.25, no_width).is_err());
+    }
+
+    #[test]
+    fn weighted_split() {
+        let values: Vec<f64> = (0..400).map(|i| ((i * 37) % 400) as f64).collect();
+        let Split { train, test, .. } =
+            train_test_split(&values, 0.3, SplitStrategy::Random { seed: 3 }).unwrap();
+        // weights keyed by the training inputs also cover the test inputs
+        let weighting = Weighting::samples(
+            train
+                .iter()
+                .map(|&(x, _)| (x, if x < 0.5 { 2. } else { 1. })),
+        );
+        let config = FitConfig::default()
+            .with_max_iters(50)
+            .with_error(ErrorOptions::default().with_weighting(weighting));
+        let model = registry::descriptor("linear").unwrap();
+        let (fit, _) = model.fit(train.clone(), 16, &config).unwrap();
+        let error = model.error(&test, fit.as_ref(), 16, &config.error);
+        assert!(error.is_finite() && error > 0.);
+    }
+
+    #[test]
+    fn folds() {
+        // a skewed channel, few pixels so the fits differ between folds
+        let values: Vec<f64> = (0..60)
+            .map(|i| ((i * 17) % 60) as f64)
+            .map(|v| v * v)
+            .collect();
+        let model = registry::descriptor("lloyd-max").unwrap();
+        let sweep = Sweep::new(1..4);
+        let validation = cross_validate_models(
+            &[model],
+            &values,
+            3,
+            SplitStrategy::Random { seed: 4 },
+            &FitConfig::default(),
+            &sweep,
+        )
+        .unwrap();
+        assert_eq!(validation.len(), 1);
+        let validation = &validation[0];
+        assert_eq!(validation.name, "lloyd-max");
+        assert_eq!(validation.levels, sweep.levels);
+        assert_eq!(validation.train.len(), 3);
+        assert_eq!(validation.test.len(), 3);
+        for band in validation.train.iter().chain(&validation.test) {
+            assert!(
+                band.lower <= band.mean && band.mean <= band.upper,
+                "{:?}",
+                band
+            );
+        }
+        // the codebooks are optimal for their training folds
+        assert!(
+            validation.generalization_gap().iter().all(|&gap| gap > 0.),
+            "{:?}",
+            validation
+        );
+        assert!(cross_validate_models(
+            &[],
+            &values,
+            1,
+            SplitStrategy::Random { seed: 4 },
+            &FitConfig::default(),
+            &sweep
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{CreateFitFn, FitFn};
+
+/// Non-parametric transfer curve given directly by a normalized distribution.
+///
+/// Mapping values through the CDF of their own distribution equalizes the
+/// histogram, which makes this the baseline the parametric models have to
+/// compete with.
+#[derive(Debug, Clone)]
+pub struct EmpiricalCdf {
+    params: Vec<f64>,
+}
+
+impl EmpiricalCdf {
+    pub fn new(dist: &[(f64, f64)]) -> Self {
+        Self::smoothed(dist, 1)
+    }
+
+    /// Builds the lookup table from `dist` after applying a centered moving
+    /// average of `window` points to the CDF values. The averages are rescaled
+    /// to the range of the original CDF, so the truncated windows at the ends
+    /// do not keep the first and last codes from being used.
+    ///
+    /// Panics unless `window` is odd, a centered window needs a middle point.
+    pub fn smoothed(dist: &[(f64, f64)], window: usize) -> Self {
+        assert!(
+            window % 2 == 1,
+            "the smoothing window has to be odd, got {}",
+            window
+        );
+        let mut table = dist.to_vec();
+        crate::drop_duplicates(&mut table);
+        let half = window / 2;
+        let averages: Vec<f64> = (0..table.len())
+            .map(|i| {
+                let start = i.saturating_sub(half);
+                let end = (i + half + 1).min(table.len());
+                table[start..end].iter().map(|(_, y)| y).sum::<f64>() / (end - start) as f64
+            })
+            .collect();
+        let (first, last) = (table[0].1, table[table.len() - 1].1);
+        let (low, high) = (averages[0], averages[averages.len() - 1]);
+        let ys = averages.iter().map(|&y| {
+            if high > low && (low, high) != (first, last) {
+                let t = (y - low) / (high - low);
+                first * (1. - t) + last * t
+            } else {
+                y
+            }
+        });
+        let xs = table.iter().map(|(x, _)| *x);
+        <Self as CreateFitFn>::new(xs.chain(ys).collect())
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    fn xs(&self) -> &[f64] {
+        &self.params[..self.len()]
+    }
+
+    fn ys(&self) -> &[f64] {
+        &self.params[self.len()..]
+    }
+}
+
+/// Piecewise linear interpolation of `value` from the sorted `from` table into
+/// `to`, clamped to the ends of the table.
+fn interpolate(from: &[f64], to: &[f64], value: f64) -> f64 {
+    let index = from.partition_point(|&v| v <= value);
+    if index == 0 {
+        return to[0];
+    }
+    if index == from.len() {
+        return to[to.len() - 1];
+    }
+    let (x0, x1) = (from[index - 1], from[index]);
+    let (y0, y1) = (to[index - 1], to[index]);
+    if x1 == x0 {
+        return y0;
+    }
+    y0 + (value - x0) * (y1 - y0) / (x1 - x0)
+}
+
+impl FitFn for EmpiricalCdf {
+    fn function(&self, x: f64) -> f64 {
+        interpolate(self.xs(), self.ys(), x)
+    }
+    fn inverse(&self, x: f64) -> f64 {
+        interpolate(self.ys(), self.xs(), x)
+    }
+    fn name(&self) -> &str {
+        "empirical"
+    }
+    fn parameters(&self) -> &[f64] {
+        &self.params
+    }
+}
+
+impl CreateFitFn for EmpiricalCdf {
+    /// Expects the table inputs followed by the table outputs.
+    fn new(params: Vec<f64>) -> Self {
+        assert!(
+            !params.is_empty() && params.len().is_multiple_of(2),
+            "an empirical cdf needs an equal, non-zero number of inputs and outputs"
+        );
+        Self { params }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_table() {
+        let dist: Vec<_> = (1..=50)
+            .map(|i| (i as f64 / 50., (i as f64 / 50.).sqrt()))
+            .collect();
+        let cdf = EmpiricalCdf::new(&dist);
+        assert_eq!(cdf.len(), 50);
+        for &(x, y) in &dist {
+            assert_eq!(cdf.function(x), y);
+            assert!((cdf.inverse(y) - x).abs() < 1e-12);
+        }
+        assert_eq!(cdf.function(-1.), dist[0].1);
+        assert_eq!(cdf.function(2.), 1.);
+
+        let smoothed = EmpiricalCdf::smoothed(&dist, 7);
+        let mut last = f64::MIN;
+        for i in 0..=200 {
+            let x = i as f64 / 200.;
+            let y = smoothed.function(x);
+            assert!(y >= last, "decreasing at {}", x);
+            last = y;
+            assert!((smoothed.inverse(y) - x).abs() < 1e-9 || x < dist[0].0);
+        }
+        // the ends of the table are kept, so every code stays reachable
+        assert_eq!(smoothed.function(0.), dist[0].1);
+        assert_eq!(smoothed.function(1.), 1.);
+        assert_ne!(smoothed.function(0.5), cdf.function(0.5));
+        assert_eq!(
+            EmpiricalCdf::smoothed(&dist, 1).parameters(),
+            cdf.parameters()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "odd")]
+    fn even_window() {
+        EmpiricalCdf::smoothed(&[(0.5, 0.5), (1., 1.)], 4);
+    }
+}
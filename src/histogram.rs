@@ -0,0 +1,162 @@
+//! Binned distributions for evaluating errors on large images.
+//!
+//! A distribution built from a [`Histogram`] has one point per non-empty bin,
+//! so [`crate::distribution_error`] runs in O(bins) instead of O(pixels).
+
+use crate::Normalization;
+
+/// Counts of non-negative values in bins of a fixed width starting at zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    width: f64,
+    counts: Vec<u64>,
+    /// Sum of the values in each bin, so bins are represented by their mean.
+    sums: Vec<f64>,
+}
+
+impl Histogram {
+    pub fn new(width: f64) -> Self {
+        assert!(width > 0., "bin width has to be positive");
+        Self {
+            width,
+            counts: Vec::new(),
+            sums: Vec::new(),
+        }
+    }
+
+    /// One bin per integer raw code.
+    pub fn from_codes(codes: impl IntoIterator<Item = u16>) -> Self {
+        let mut histogram = Self::new(1.);
+        histogram.counts = vec![0; u16::MAX as usize + 1];
+        for code in codes {
+            histogram.counts[code as usize] += 1;
+        }
+        histogram.sums = histogram
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(code, &count)| code as f64 * count as f64)
+            .collect();
+        histogram.trim();
+        histogram
+    }
+
+    pub fn from_values(values: impl IntoIterator<Item = f64>, width: f64) -> Self {
+        let mut histogram = Self::new(width);
+        for value in values {
+            histogram.add(value);
+        }
+        histogram
+    }
+
+    pub fn add(&mut self, value: f64) {
+        assert!(
+            value >= 0. && value.is_finite(),
+            "can not add {} to a histogram",
+            value
+        );
+        let bin = (value / self.width) as usize;
+        if bin >= self.counts.len() {
+            self.counts.resize(bin + 1, 0);
+            self.sums.resize(bin + 1, 0.);
+        }
+        self.counts[bin] += 1;
+        self.sums[bin] += value;
+    }
+
+    /// Merges every `factor` adjacent bins into one.
+    pub fn rebin(&self, factor: usize) -> Self {
+        assert!(factor > 0, "can not rebin by zero");
+        Self {
+            width: self.width * factor as f64,
+            counts: self.counts.chunks(factor).map(|c| c.iter().sum()).collect(),
+            sums: self.sums.chunks(factor).map(|s| s.iter().sum()).collect(),
+        }
+    }
+
+    pub fn width(&self) -> f64 {
+        self.width
+    }
+
+    /// Number of bins up to the last non-empty one.
+    pub fn bins(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The normalization [`Histogram::distribution`] applies to the values.
+    pub fn normalization(&self) -> Normalization {
+        let max = self
+            .counts
+            .iter()
+            .zip(&self.sums)
+            .rev()
+            .find(|(&count, _)| count > 0)
+            .map_or(1., |(&count, &sum)| sum / count as f64);
+        Normalization::new(0., max)
+    }
+
+    /// The normalized cumulative distribution, in the same form as
+    /// [`crate::normalize_distribution`] produces for the individual values.
+    pub fn distribution(&self) -> Vec<(f64, f64)> {
+        let points: Vec<(f64, u64)> = self
+            .counts
+            .iter()
+            .zip(&self.sums)
+            .filter(|(&count, _)| count > 0)
+            .map(|(&count, &sum)| (sum / count as f64, count))
+            .collect();
+        let Some(&(max_x, _)) = points.last() else {
+            return Vec::new();
+        };
+        let total = self.total() as f64;
+        let mut cumulative = 0;
+        points
+            .into_iter()
+            .map(|(x, count)| {
+                cumulative += count;
+                (x / max_x, cumulative as f64 / total)
+            })
+            .collect()
+    }
+
+    fn trim(&mut self) {
+        let len = self
+            .counts
+            .iter()
+            .rposition(|&c| c > 0)
+            .map_or(0, |i| i + 1);
+        self.counts.truncate(len);
+        self.sums.truncate(len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_sorted_distribution() {
+        let codes: Vec<u16> = (0..1000u32).map(|i| ((i * i) % 997) as u16 + 3).collect();
+
+        let mut expected = crate::integrate_distribution(codes.iter().map(|&c| c as f64).collect());
+        crate::drop_duplicates(&mut expected);
+        let expected = crate::normalize_distribution(&expected);
+        assert_eq!(
+            Histogram::from_codes(codes.iter().copied()).distribution(),
+            expected
+        );
+
+        let values = Histogram::from_values(codes.iter().map(|&c| c as f64), 1.);
+        assert_eq!(values.distribution(), expected);
+
+        let coarse = values.rebin(100);
+        assert_eq!(coarse.bins(), 10);
+        assert_eq!(coarse.total(), 1000);
+        assert_eq!(coarse.distribution().last(), Some(&(1., 1.)));
+        assert_eq!(values.normalization(), Normalization::new(0., 999.));
+    }
+}
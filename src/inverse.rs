@@ -0,0 +1,349 @@
+//! Numeric inversion for models which only provide a forward function.
+
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Result};
+
+use crate::{Bound, CreateFitFn, FitFn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootFinder {
+    Brent,
+    /// Newton's method on a numeric derivative, falling back to bisection
+    /// whenever a step would leave the bracket.
+    Newton,
+}
+
+/// Gives a forward-only function a [`FitFn::inverse`] by root finding inside
+/// of a bracket.
+///
+/// The function has to be monotone (increasing or decreasing) on the bracket.
+/// [`FitFn::inverse`] saturates at the ends of the bracket for values outside
+/// of the covered range, [`NumericInverse::try_inverse`] reports them as
+/// errors instead.
+pub struct NumericInverse<F: Fn(f64) -> f64> {
+    function: F,
+    name: String,
+    pub bracket: (f64, f64),
+    pub tolerance: f64,
+    pub max_iters: usize,
+    pub method: RootFinder,
+}
+
+impl<F: Fn(f64) -> f64> NumericInverse<F> {
+    pub fn new(name: impl Into<String>, function: F) -> Self {
+        Self {
+            function,
+            name: name.into(),
+            bracket: (0., 1.),
+            tolerance: 1e-12,
+            max_iters: 100,
+            method: RootFinder::Brent,
+        }
+    }
+
+    pub fn with_bracket(mut self, lower: f64, upper: f64) -> Self {
+        self.bracket = (lower, upper);
+        self
+    }
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+    pub fn with_max_iters(mut self, max_iters: usize) -> Self {
+        self.max_iters = max_iters;
+        self
+    }
+    pub fn with_method(mut self, method: RootFinder) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Finds `x` inside of the bracket with `function(x) == y`.
+    pub fn try_inverse(&self, y: f64) -> Result<f64> {
+        let (lower, upper) = self.bracket;
+        ensure!(y.is_finite(), "can not invert non-finite value {}", y);
+        ensure!(lower < upper, "empty bracket [{}, {}]", lower, upper);
+        let g = |x: f64| (self.function)(x) - y;
+        let (g_lower, g_upper) = (g(lower), g(upper));
+        ensure!(
+            g_lower.is_finite() && g_upper.is_finite(),
+            "{} is not finite at the ends of [{}, {}]",
+            self.name,
+            lower,
+            upper
+        );
+        if g_lower == 0. {
+            return Ok(lower);
+        }
+        if g_upper == 0. {
+            return Ok(upper);
+        }
+        ensure!(
+            g_lower.signum() != g_upper.signum(),
+            "{} does not reach {} on [{}, {}]",
+            self.name,
+            y,
+            lower,
+            upper
+        );
+        match self.method {
+            RootFinder::Brent => self.brent(g, (lower, g_lower), (upper, g_upper)),
+            RootFinder::Newton => self.newton(g, (lower, g_lower), (upper, g_upper)),
+        }
+    }
+
+    fn brent(&self, g: impl Fn(f64) -> f64, a: (f64, f64), b: (f64, f64)) -> Result<f64> {
+        let ((mut a, mut fa), (mut b, mut fb)) = (a, b);
+        let (mut c, mut fc) = (b, fb);
+        let mut d = b - a;
+        let mut e = d;
+        for _ in 0..self.max_iters {
+            if fb.signum() == fc.signum() {
+                c = a;
+                fc = fa;
+                d = b - a;
+                e = d;
+            }
+            if fc.abs() < fb.abs() {
+                a = b;
+                b = c;
+                c = a;
+                fa = fb;
+                fb = fc;
+                fc = fa;
+            }
+            let tol = 2. * f64::EPSILON * b.abs() + 0.5 * self.tolerance;
+            let m = 0.5 * (c - b);
+            if m.abs() <= tol || fb == 0. {
+                return Ok(b);
+            }
+            if e.abs() >= tol && fa.abs() > fb.abs() {
+                // inverse quadratic interpolation, or secant if only two points differ
+                let s = fb / fa;
+                let (mut p, mut q) = if a == c {
+                    (2. * m * s, 1. - s)
+                } else {
+                    let q = fa / fc;
+                    let r = fb / fc;
+                    (
+                        s * (2. * m * q * (q - r) - (b - a) * (r - 1.)),
+                        (q - 1.) * (r - 1.) * (s - 1.),
+                    )
+                };
+                if p > 0. {
+                    q = -q;
+                }
+                p = p.abs();
+                if 2. * p < (3. * m * q - (tol * q).abs()).min((e * q).abs()) {
+                    e = d;
+                    d = p / q;
+                } else {
+                    d = m;
+                    e = d;
+                }
+            } else {
+                d = m;
+                e = d;
+            }
+            a = b;
+            fa = fb;
+            b += if d.abs() > tol { d } else { tol.copysign(m) };
+            fb = g(b);
+        }
+        bail!(
+            "inverting {} did not converge within {} iterations",
+            self.name,
+            self.max_iters
+        )
+    }
+
+    fn newton(&self, g: impl Fn(f64) -> f64, a: (f64, f64), b: (f64, f64)) -> Result<f64> {
+        let ((mut lower, g_lower), (mut upper, _)) = (a, b);
+        let lower_sign = g_lower.signum();
+        let mut x = 0.5 * (lower + upper);
+        for _ in 0..self.max_iters {
+            let gx = g(x);
+            if gx == 0. {
+                return Ok(x);
+            }
+            if gx.signum() == lower_sign {
+                lower = x;
+            } else {
+                upper = x;
+            }
+            let h = 1e-7 * x.abs().max(1.);
+            let derivative = (g(x + h) - g(x - h)) / (2. * h);
+            let newton = x - gx / derivative;
+            let next =
+                if newton.is_finite() && newton > lower.min(upper) && newton < lower.max(upper) {
+                    newton
+                } else {
+                    0.5 * (lower + upper)
+                };
+            if (next - x).abs() <= self.tolerance {
+                return Ok(next);
+            }
+            x = next;
+        }
+        bail!(
+            "inverting {} did not converge within {} iterations",
+            self.name,
+            self.max_iters
+        )
+    }
+}
+
+impl<F: Fn(f64) -> f64> FitFn for NumericInverse<F> {
+    fn function(&self, x: f64) -> f64 {
+        (self.function)(x)
+    }
+    fn inverse(&self, x: f64) -> f64 {
+        self.try_inverse(x).unwrap_or_else(|_| {
+            // saturate at the end of the bracket closest to the requested value
+            let (lower, upper) = self.bracket;
+            let distance = |bound: f64| ((self.function)(bound) - x).abs();
+            if distance(lower) <= distance(upper) {
+                lower
+            } else {
+                upper
+            }
+        })
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// A model which only defines its forward function. Wrapped in [`Inverted`]
+/// it can be fitted like any [`CreateFitFn`], e.g. with
+/// [`crate::models::fit`] or
+/// [`ModelDescriptor::parametric`](crate::registry::ModelDescriptor::parametric).
+pub trait ForwardModel: Send + Sync + 'static {
+    fn new(params: Vec<f64>) -> Self;
+    fn function(&self, x: f64) -> f64;
+    fn name(&self) -> &str;
+    fn parameters(&self) -> &[f64];
+    /// See [`CreateFitFn::bound`].
+    fn bound(_index: usize) -> Bound {
+        Bound::UNBOUNDED
+    }
+}
+
+type Forward = Box<dyn Fn(f64) -> f64 + Send + Sync>;
+
+/// A [`ForwardModel`] inverted numerically on [0, 1] with the default
+/// settings of [`NumericInverse`].
+pub struct Inverted<T: ForwardModel> {
+    model: Arc<T>,
+    inverse: NumericInverse<Forward>,
+}
+
+impl<T: ForwardModel> Inverted<T> {
+    pub fn model(&self) -> &T {
+        &self.model
+    }
+}
+
+impl<T: ForwardModel> FitFn for Inverted<T> {
+    fn function(&self, x: f64) -> f64 {
+        self.model.function(x)
+    }
+    fn inverse(&self, x: f64) -> f64 {
+        self.inverse.inverse(x)
+    }
+    fn name(&self) -> &str {
+        self.model.name()
+    }
+    fn parameters(&self) -> &[f64] {
+        self.model.parameters()
+    }
+}
+
+impl<T: ForwardModel> CreateFitFn for Inverted<T> {
+    fn new(params: Vec<f64>) -> Self {
+        let model = Arc::new(T::new(params));
+        let forward = model.clone();
+        let forward: Forward = Box::new(move |x| forward.function(x));
+        Self {
+            inverse: NumericInverse::new(model.name(), forward),
+            model,
+        }
+    }
+    fn bound(index: usize) -> Bound {
+        T::bound(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brent_and_newton() {
+        for method in [RootFinder::Brent, RootFinder::Newton] {
+            let increasing = NumericInverse::new("cube", |x: f64| x.powi(3)).with_method(method);
+            let x = increasing.try_inverse(0.125).unwrap();
+            assert!((x - 0.5).abs() < 1e-9, "{:?}: {}", method, x);
+
+            let decreasing = NumericInverse::new("decay", |x: f64| (-x).exp())
+                .with_bracket(0., 10.)
+                .with_method(method);
+            let x = decreasing.try_inverse(0.5).unwrap();
+            assert!((x - 2f64.ln()).abs() < 1e-9, "{:?}: {}", method, x);
+            assert!(decreasing.try_inverse(2.).is_err());
+            assert_eq!(decreasing.inverse(2.), 0.);
+        }
+    }
+
+    /// `x^gamma`, which is inverted numerically for the test.
+    struct Gamma(Vec<f64>);
+
+    impl ForwardModel for Gamma {
+        fn new(params: Vec<f64>) -> Self {
+            Self(params)
+        }
+        fn function(&self, x: f64) -> f64 {
+            x.max(0.).powf(self.0[0])
+        }
+        fn name(&self) -> &str {
+            "gamma"
+        }
+        fn parameters(&self) -> &[f64] {
+            &self.0
+        }
+        fn bound(_index: usize) -> Bound {
+            Bound::at_least(0.1)
+        }
+    }
+
+    #[test]
+    fn inverted_model() {
+        let gamma = <Inverted<Gamma> as CreateFitFn>::new(vec![0.5]);
+        assert_eq!(gamma.name(), "gamma");
+        assert!((gamma.inverse(0.5) - 0.25).abs() < 1e-9);
+        assert_eq!(Inverted::<Gamma>::bound(0), Bound::at_least(0.1));
+    }
+
+    #[cfg(feature = "fitting")]
+    #[test]
+    fn fit_inverted_model() {
+        use crate::models::{fit, FitConfig, ValidationPolicy};
+        // values distributed like x^2, a steep power like x^3 wastes most
+        // codes on the few bright values
+        let dist: Vec<_> = (1..=100)
+            .map(|i| ((i as f64 / 100.).powi(2), i as f64 / 100.))
+            .collect();
+        let config =
+            FitConfig::default().with_validation(ValidationPolicy::Reject { tolerance: 1e-6 });
+        let (gamma, report) =
+            fit::<Inverted<Gamma>>(dist.clone(), 16, vec![vec![3.], vec![2.5]], &config).unwrap();
+        assert!(gamma.model().0[0] < 1.5, "{:?}", gamma.parameters());
+        let start = <Inverted<Gamma> as CreateFitFn>::new(vec![3.]);
+        assert!(
+            crate::distribution_error(&dist, &gamma, 16)
+                < crate::distribution_error(&dist, &start, 16)
+        );
+        assert!(report.validation.is_some());
+    }
+}
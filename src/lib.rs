@@ -3,12 +3,39 @@ use rand::distributions::Distribution;
 #[cfg(feature = "generation")]
 use statrs::distribution::Normal;
 
-use rayon::prelude::*;
-
 pub mod sum;
 
 pub mod packing;
 
+pub mod empirical;
+
+pub mod lloyd_max;
+
+pub mod validate;
+
+pub mod inverse;
+
+pub mod metric;
+
+pub mod quantize;
+
+pub mod histogram;
+
+#[cfg(feature = "fitting")]
+pub mod persist;
+
+#[cfg(feature = "fitting")]
+pub mod registry;
+
+#[cfg(feature = "fitting")]
+pub mod bootstrap;
+
+#[cfg(feature = "fitting")]
+pub mod crossval;
+
+#[cfg(feature = "fitting")]
+pub mod sweep;
+
 pub fn integrate_distribution(mut distribution: Vec<f64>) -> Vec<(f64, f64)> {
     distribution.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
     let mut result = Vec::with_capacity(distribution.len());
@@ -28,12 +55,20 @@ pub fn drop_duplicates(distribution: &mut Vec<(f64, f64)>) {
 }
 
 pub fn normalize_distribution(distribution: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    normalize_raw_distribution(distribution).0
+}
+
+/// Normalizes a distribution of raw values like [`normalize_distribution`]
+/// and returns the [`Normalization`] it applied to the values, to convert
+/// errors on the result back into raw units.
+pub fn normalize_raw_distribution(distribution: &[(f64, f64)]) -> (Dist, Normalization) {
     let max_y = distribution.last().unwrap().1;
-    let max_x = distribution.last().unwrap().0;
-    distribution
+    let normalization = Normalization::new(0., distribution.last().unwrap().0);
+    let normalized = distribution
         .iter()
-        .map(|&(x, y)| (x / max_x, y / max_y))
-        .collect()
+        .map(|&(x, y)| (normalization.apply(x), y / max_y))
+        .collect();
+    (normalized, normalization)
 }
 
 #[cfg(feature = "generation")]
@@ -51,50 +86,54 @@ pub fn generate_normal_distribution(
     result
 }
 
+/// Encodes `value` with [`quantize::Rounding::Floor`].
 pub fn encode(value: f64, fit: &dyn FitFn, samples: u64) -> u64 {
-    let mut mapped_value = fit.function(value);
-    if !mapped_value.is_finite() {
-        mapped_value = 0.;
-    }
-    assert!(!mapped_value.is_nan(), "mapped value is NaN");
-    (mapped_value * samples as f64).clamp(0., samples as f64) as u64
+    quantize::Quantizer::default().encode(value, fit, samples, (0, 0), &mut || 0.)
 }
+/// Decodes `value` with [`quantize::Reconstruction::LowerEdge`].
 pub fn decode(value: u64, fit: &dyn FitFn, samples: u64) -> f64 {
-    let mut x = value as f64 / samples as f64;
-    if !x.is_finite() {
-        x = 0.;
-    }
-    x = fit.inverse(x);
-    if !x.is_finite() {
-        x = 0.;
-    }
-    x
+    quantize::Quantizer::default().decode(value, fit, samples)
 }
 
 pub fn distribution_error<T: FitFn>(data: &[(f64, f64)], fun: T, quantization: u64) -> f64 {
-    let mut last_y = 0.;
-    let iter = data.iter().map(|&(x, y)| {
-        let encoded = encode(x, &fun, quantization);
-        let decoded = decode(encoded, &fun, quantization);
+    distribution_error_with(data, fun, quantization, &metric::ErrorOptions::default())
+}
 
-        let weight = |x: f64| (-(x).abs().clamp(0., 1.)).exp();
-        let error = ((decoded - x) * 100.).powi(2) * (y - last_y); // * weight(x));
-                                                                   //dbg!(x, (y - last_y) * weight(x));
+/// Quantization error of `fun` with `quantization` levels on the normalized
+/// distribution `data`, measured as configured in `options`.
+pub fn distribution_error_with<T: FitFn>(
+    data: &[(f64, f64)],
+    fun: T,
+    quantization: u64,
+    options: &metric::ErrorOptions,
+) -> f64 {
+    let metric = options.metric;
+    let quantizer = &options.quantizer;
+    let fun = &fun as &dyn FitFn;
+    let mut last_y = 0.;
+    let iter = data.iter().flat_map(|&(x, y)| {
+        let increment = (y - last_y) * options.weighting.weight(x);
         last_y = y;
-        //dbg!(error, x, weight(x));
-
-        assert!(
-            error.is_finite(),
-            "encontered non-finite error {} for encoded value {}, decoded value {}, x: {}",
-            error,
-            encoded,
-            decoded,
-            encoded as f64 / quantization as f64,
-        );
-        error
+        // the expected error over all codes `x` can be encoded to
+        quantizer
+            .outcomes(x, fun, quantization)
+            .filter(|&(_, probability)| probability > 0.)
+            .map(move |(encoded, probability)| {
+                let decoded = quantizer.decode(encoded, fun, quantization);
+                let error = metric.residual(x, decoded);
+                assert!(
+                    error.is_finite(),
+                    "encontered non-finite error {} for encoded value {}, decoded value {}, x: {}",
+                    error,
+                    encoded,
+                    decoded,
+                    encoded as f64 / quantization as f64,
+                );
+                (error, increment * probability)
+            })
     });
 
-    crate::sum::Sum::from_iter(iter).sum().sqrt()
+    metric.aggregate(iter)
 }
 
 pub fn inverse_of_distribution(distribution: &[(f64, f64)], y: f64) -> f64 {
@@ -106,6 +145,8 @@ pub fn inverse_of_distribution(distribution: &[(f64, f64)], y: f64) -> f64 {
         .unwrap()
 }
 
+/// Bisection search for the inverse of `f`, see [`inverse::NumericInverse`]
+/// for a configurable variant which does not panic.
 pub fn inverse_of_fn(f: impl Fn(f64) -> f64, x: f64) -> f64 {
     let mut a = -100.0;
     let mut b = 100.0;
@@ -129,10 +170,40 @@ pub fn inverse_of_fn(f: impl Fn(f64) -> f64, x: f64) -> f64 {
 }
 type Dist = Vec<(f64, f64)>;
 
+/// Maps raw sensor values into the unit interval the fit functions operate on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Normalization {
+    pub offset: f64,
+    pub scale: f64,
+}
+
+impl Normalization {
+    pub fn new(offset: f64, scale: f64) -> Self {
+        Self { offset, scale }
+    }
+    pub fn apply(&self, raw: f64) -> f64 {
+        (raw - self.offset) / self.scale
+    }
+    pub fn restore(&self, normalized: f64) -> f64 {
+        normalized * self.scale + self.offset
+    }
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Self::new(0., 1.)
+    }
+}
+
 pub trait FitFn {
     fn function(&self, x: f64) -> f64;
     fn inverse(&self, x: f64) -> f64;
     fn name(&self) -> &str;
+    /// The parameter vector describing this function, empty for functions
+    /// which can not be reconstructed from parameters.
+    fn parameters(&self) -> &[f64] {
+        &[]
+    }
 }
 
 impl<T: FitFn> FitFn for &T {
@@ -145,6 +216,9 @@ impl<T: FitFn> FitFn for &T {
     fn name(&self) -> &str {
         (*self).name()
     }
+    fn parameters(&self) -> &[f64] {
+        (*self).parameters()
+    }
 }
 impl FitFn for &dyn FitFn {
     fn function(&self, x: f64) -> f64 {
@@ -156,12 +230,83 @@ impl FitFn for &dyn FitFn {
     fn name(&self) -> &str {
         (*self).name()
     }
+    fn parameters(&self) -> &[f64] {
+        (*self).parameters()
+    }
 }
 
 pub trait CreateFitFn: FitFn + Sized {
     fn new(_params: Vec<f64>) -> Self {
         unimplemented!()
     }
+    /// The valid range of the parameter at `index`. Fitting only ever
+    /// evaluates parameters inside of these bounds.
+    fn bound(_index: usize) -> Bound {
+        Bound::UNBOUNDED
+    }
+}
+
+/// Closed interval of valid values for a single model parameter.
+///
+/// Solvers work on an unconstrained parameter space which is mapped into the
+/// interval by [`Bound::transform`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bound {
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl Bound {
+    pub const UNBOUNDED: Bound = Bound {
+        lower: f64::NEG_INFINITY,
+        upper: f64::INFINITY,
+    };
+
+    pub fn at_least(lower: f64) -> Self {
+        Self {
+            lower,
+            upper: f64::INFINITY,
+        }
+    }
+    pub fn at_most(upper: f64) -> Self {
+        Self {
+            lower: f64::NEG_INFINITY,
+            upper,
+        }
+    }
+    pub fn between(lower: f64, upper: f64) -> Self {
+        Self { lower, upper }
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        (self.lower..=self.upper).contains(&value)
+    }
+
+    /// Maps an unconstrained value into the bound.
+    pub fn transform(&self, value: f64) -> f64 {
+        match (self.lower.is_finite(), self.upper.is_finite()) {
+            (true, true) => self.lower + (self.upper - self.lower) / (1. + (-value).exp()),
+            (true, false) => self.lower + value.exp(),
+            (false, true) => self.upper - value.exp(),
+            (false, false) => value,
+        }
+    }
+
+    /// Maps a value inside of the bound back to the unconstrained space.
+    /// Values on the boundary are moved slightly inside.
+    pub fn inverse_transform(&self, value: f64) -> f64 {
+        const MARGIN: f64 = 1e-9;
+        match (self.lower.is_finite(), self.upper.is_finite()) {
+            (true, true) => {
+                let t =
+                    ((value - self.lower) / (self.upper - self.lower)).clamp(MARGIN, 1. - MARGIN);
+                (t / (1. - t)).ln()
+            }
+            (true, false) => (value - self.lower).max(MARGIN).ln(),
+            (false, true) => (self.upper - value).max(MARGIN).ln(),
+            (false, false) => value,
+        }
+    }
 }
 
 pub struct SimpleFitFn<F: Fn(f64) -> f64, I: Fn(f64) -> f64> {
@@ -186,52 +331,72 @@ impl<F: Fn(f64) -> f64, I: Fn(f64) -> f64> FitFn for SimpleFitFn<F, I> {
 }
 
 #[cfg(feature = "fitting")]
-pub fn calculate_error_functions(train: &Dist, test: &Dist) -> (Vec<String>, Vec<Vec<f64>>) {
-    let names = vec![
-        "linear".to_string(),
-        "log".to_string(),
-        "pow".to_string(),
-        "exp".to_string(),
-    ];
-    let mut errors = (0..names.len()).map(|_| Vec::new()).collect::<Vec<_>>();
-
-    for i in 0..names.len() {
-        errors[i] = calculate_error_function(train, i, test);
-    }
-    (names, errors)
+pub fn calculate_error_functions(
+    train: &Dist,
+    test: &Dist,
+    config: &models::FitConfig,
+) -> anyhow::Result<(Vec<String>, Vec<sweep::SweepErrors>)> {
+    calculate_error_functions_with(train, test, config, &sweep::Sweep::default())
 }
 
-pub fn calculate_error_function(train: &[(f64, f64)], i: usize, test: &[(f64, f64)]) -> Vec<f64> {
-    let bits: Vec<_> = (0..12).collect();
-    let errors: Vec<_> = bits
-        .par_iter()
-        .map(|bits| {
-            let levels = 1 << bits;
-            let fn_ = fit_function(train.to_vec(), levels, i);
-            let error = distribution_error(test, fn_.as_ref(), levels);
-            error
-        })
-        .collect();
-    errors
+/// Errors of every registered model on `test` for the bit depths of `sweep`,
+/// see [`calculate_error_function_with`].
+#[cfg(feature = "fitting")]
+pub fn calculate_error_functions_with(
+    train: &Dist,
+    test: &Dist,
+    config: &models::FitConfig,
+    sweep: &sweep::Sweep,
+) -> anyhow::Result<(Vec<String>, Vec<sweep::SweepErrors>)> {
+    let names = registry::names();
+    let errors = names
+        .iter()
+        .map(|name| calculate_error_function_with(train, name, test, config, sweep))
+        .collect::<anyhow::Result<_>>()?;
+    Ok((names, errors))
+}
+
+#[cfg(feature = "fitting")]
+pub fn calculate_error_function(
+    train: &[(f64, f64)],
+    name: &str,
+    test: &[(f64, f64)],
+    config: &models::FitConfig,
+) -> anyhow::Result<sweep::SweepErrors> {
+    calculate_error_function_with(train, name, test, config, &sweep::Sweep::default())
 }
+
+/// Errors of the model `name` on `test` for the bit depths of `sweep`, as
+/// `(levels, error)` pairs in order of increasing level count.
 #[cfg(feature = "fitting")]
-pub fn fit_functions(dist: Dist, levels: u64) -> Vec<Box<dyn FitFn>> {
-    vec![
-        fit_function(dist.clone(), levels, 0),
-        fit_function(dist.clone(), levels, 1),
-        fit_function(dist.clone(), levels, 2),
-        fit_function(dist, levels, 3),
-    ]
+pub fn calculate_error_function_with(
+    train: &[(f64, f64)],
+    name: &str,
+    test: &[(f64, f64)],
+    config: &models::FitConfig,
+    sweep: &sweep::Sweep,
+) -> anyhow::Result<sweep::SweepErrors> {
+    sweep.errors(&registry::descriptor(name)?, train, test, config)
 }
 #[cfg(feature = "fitting")]
-pub fn fit_function(dist: Dist, levels: u64, index: usize) -> Box<dyn FitFn> {
-    match index {
-        0 => Box::new(models::OptimizedLin::new(dist, levels)),
-        1 => Box::new(models::OptimizedLog::new(dist, levels)),
-        2 => Box::new(models::OptimizedPow::new(dist, levels)),
-        3 => Box::new(models::OptimizedExp::new(dist, levels)),
-        _ => panic!("Unknown fit function"),
-    }
+pub fn fit_functions(
+    dist: Dist,
+    levels: u64,
+    config: &models::FitConfig,
+) -> anyhow::Result<Vec<(Box<dyn FitFn>, models::FitReport)>> {
+    registry::descriptors()
+        .iter()
+        .map(|model| model.fit(dist.clone(), levels, config))
+        .collect()
+}
+#[cfg(feature = "fitting")]
+pub fn fit_function(
+    dist: Dist,
+    levels: u64,
+    name: &str,
+    config: &models::FitConfig,
+) -> anyhow::Result<(Box<dyn FitFn>, models::FitReport)> {
+    registry::descriptor(name)?.fit(dist, levels, config)
 }
 
 pub fn linearize_distribution(distribution: &[(f64, f64)], fit: &impl FitFn) -> Vec<(f64, f64)> {
@@ -249,6 +414,41 @@ pub mod plot;
 mod tests {
     use super::*;
 
+    #[test]
+    fn raw_distribution() {
+        let raw = [(512., 1.), (1024., 3.), (2048., 4.)];
+        let (dist, normalization) = normalize_raw_distribution(&raw);
+        assert_eq!(dist, vec![(0.25, 0.25), (0.5, 0.75), (1., 1.)]);
+        assert_eq!(normalization, Normalization::new(0., 2048.));
+        assert_eq!(normalization.restore(dist[0].0), 512.);
+        assert_eq!(normalize_distribution(&raw), dist);
+    }
+
+    #[test]
+    fn bound_transform() {
+        let bounds = [
+            Bound::UNBOUNDED,
+            Bound::at_least(0.1),
+            Bound::at_most(-2.),
+            Bound::between(-1., 3.),
+        ];
+        for bound in bounds {
+            for value in [-3., -2., -1., -0.5, 0., 0.1, 0.5, 2.9, 3.] {
+                if !bound.contains(value) {
+                    continue;
+                }
+                let unconstrained = bound.inverse_transform(value);
+                assert!(unconstrained.is_finite(), "{:?} {}", bound, value);
+                let restored = bound.transform(unconstrained);
+                assert!(bound.contains(restored), "{:?} {}", bound, value);
+                assert!((restored - value).abs() < 1e-8, "{:?} {}", bound, value);
+            }
+            for unconstrained in [-50., -1., 0., 1., 50.] {
+                assert!(bound.contains(bound.transform(unconstrained)));
+            }
+        }
+    }
+
     #[test]
     fn inverse() {
         let inverse = inverse_of_fn(|x| 1. / x, 0.5);
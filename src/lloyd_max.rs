@@ -0,0 +1,174 @@
+use crate::metric::ErrorOptions;
+use crate::{CreateFitFn, FitFn};
+
+const MAX_ITERATIONS: usize = 500;
+const TOLERANCE: f64 = 1e-12;
+
+/// Scalar quantizer given by explicit decision thresholds and reconstruction
+/// levels.
+///
+/// Used as a [`FitFn`] the codebook maps every input into the middle of the
+/// code band of its cell, so `distribution_error(data, &codebook, n)` measures
+/// exactly the error of the codebook with `n` levels. Other
+/// [`crate::quantize::Quantizer`]s would round or dither the code bands and
+/// measure a different quantizer, use [`Codebook::distribution_error`] instead.
+#[derive(Debug, Clone)]
+pub struct Codebook {
+    /// The `n - 1` thresholds followed by the `n` reconstruction levels.
+    params: Vec<f64>,
+}
+
+impl Codebook {
+    pub fn from_levels(levels: Vec<f64>) -> Self {
+        let thresholds = levels.windows(2).map(|w| (w[0] + w[1]) / 2.);
+        <Self as CreateFitFn>::new(thresholds.chain(levels.iter().copied()).collect())
+    }
+
+    /// Computes the Lloyd-Max quantizer with `levels` reconstruction levels for
+    /// a normalized distribution, minimizing the mean squared error.
+    pub fn lloyd_max(dist: &[(f64, f64)], levels: u64) -> Self {
+        let levels = levels.max(1) as usize;
+        let mut last_y = 0.;
+        let samples: Vec<(f64, f64)> = dist
+            .iter()
+            .map(|&(x, y)| {
+                let weight = y - last_y;
+                last_y = y;
+                (x, weight)
+            })
+            .collect();
+
+        // start from the quantiles of the distribution
+        let max_y = dist.last().map_or(1., |&(_, y)| y);
+        let mut codebook = Self::from_levels(
+            (0..levels)
+                .map(|i| {
+                    let y = (i as f64 + 0.5) / levels as f64 * max_y;
+                    let index = dist.partition_point(|&(_, y_)| y_ < y);
+                    dist.get(index).or(dist.last()).map_or(0., |&(x, _)| x)
+                })
+                .collect(),
+        );
+
+        for _ in 0..MAX_ITERATIONS {
+            let mut sums = vec![(0., 0.); levels];
+            for &(x, weight) in &samples {
+                let cell = &mut sums[codebook.encode(x) as usize];
+                cell.0 += x * weight;
+                cell.1 += weight;
+            }
+            let new_levels: Vec<f64> = sums
+                .iter()
+                .zip(codebook.levels())
+                .map(|(&(sum, weight), &old)| if weight > 0. { sum / weight } else { old })
+                .collect();
+            let change = new_levels
+                .iter()
+                .zip(codebook.levels())
+                .map(|(new, old)| (new - old).abs())
+                .fold(0., f64::max);
+            codebook = Self::from_levels(new_levels);
+            if change < TOLERANCE {
+                break;
+            }
+        }
+        codebook
+    }
+
+    /// Error of the codebook on a normalized distribution, measured with the
+    /// metric and weighting of `options`. The quantizer of `options` is
+    /// ignored, the codebook is the quantizer.
+    pub fn distribution_error(&self, dist: &[(f64, f64)], options: &ErrorOptions) -> f64 {
+        let metric = options.metric;
+        let mut last_y = 0.;
+        metric.aggregate(dist.iter().map(|&(x, y)| {
+            let weight = (y - last_y) * options.weighting.weight(x);
+            last_y = y;
+            let decoded = self.decode(self.encode(x));
+            (metric.residual(x, decoded), weight)
+        }))
+    }
+
+    pub fn thresholds(&self) -> &[f64] {
+        &self.params[..self.params.len() / 2]
+    }
+
+    pub fn levels(&self) -> &[f64] {
+        &self.params[self.params.len() / 2..]
+    }
+
+    pub fn encode(&self, x: f64) -> u64 {
+        self.thresholds().partition_point(|&t| t < x) as u64
+    }
+
+    pub fn decode(&self, code: u64) -> f64 {
+        let levels = self.levels();
+        levels[(code as usize).min(levels.len() - 1)]
+    }
+}
+
+impl FitFn for Codebook {
+    fn function(&self, x: f64) -> f64 {
+        (self.encode(x) as f64 + 0.5) / self.levels().len() as f64
+    }
+    fn inverse(&self, x: f64) -> f64 {
+        let code = (x * self.levels().len() as f64 + 1e-9).floor().max(0.);
+        self.decode(code as u64)
+    }
+    fn name(&self) -> &str {
+        "lloyd-max"
+    }
+    fn parameters(&self) -> &[f64] {
+        &self.params
+    }
+}
+
+impl CreateFitFn for Codebook {
+    /// Expects the `n - 1` thresholds followed by the `n` levels.
+    fn new(params: Vec<f64>) -> Self {
+        assert!(
+            params.len() % 2 == 1,
+            "a codebook needs one threshold less than it has levels"
+        );
+        Self { params }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metric::ErrorMetric;
+    use crate::quantize::{Quantizer, Reconstruction};
+
+    #[test]
+    fn uniform_distribution() {
+        let dist: Vec<_> = (1..=1000)
+            .map(|i| (i as f64 / 1000., i as f64 / 1000.))
+            .collect();
+        let codebook = Codebook::lloyd_max(&dist, 4);
+        for (level, expected) in codebook.levels().iter().zip([0.125, 0.375, 0.625, 0.875]) {
+            assert!((level - expected).abs() < 1e-3, "{} != {}", level, expected);
+        }
+        assert_eq!(codebook.decode(codebook.encode(0.3)), codebook.levels()[1]);
+    }
+
+    #[test]
+    fn direct_error() {
+        let dist: Vec<_> = (1..=100)
+            .map(|i| ((i as f64 / 100.).powi(2), i as f64 / 100.))
+            .collect();
+        let codebook = Codebook::lloyd_max(&dist, 8);
+        for metric in [ErrorMetric::Rmse, ErrorMetric::MaxAbs] {
+            let options = ErrorOptions::default().with_metric(metric);
+            let direct = codebook.distribution_error(&dist, &options);
+            let through_fit = crate::distribution_error_with(&dist, &codebook, 8, &options);
+            assert!((direct - through_fit).abs() < 1e-9);
+            // other quantizers do not change the codebook
+            let midpoint = options.with_quantizer(Quantizer {
+                reconstruction: Reconstruction::InputMidpoint,
+                ..Quantizer::default()
+            });
+            assert_eq!(codebook.distribution_error(&dist, &midpoint), direct);
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use std::{error::Error, thread::JoinHandle};
 
 use autoquant::{
-    packing::ErrorFunction,
-    plot::{plot_channels, plot_errors, plot_errors_with_bits},
+    crossval::SplitStrategy,
+    histogram::Histogram,
+    metric::ErrorUnit,
+    packing::{allocate_bits_for, channel_bits, Constraints, DynamicErrorFunction, Objective},
+    plot::{plot_allocations, plot_channels, plot_errors, plot_errors_with_bits},
+    Normalization,
 };
 use rawloader::RawImage;
 
@@ -18,6 +22,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Merged: {:?}", merged);
     */
 
+    // an optional second argument sets the number of spline knots
+    if let Some(knots) = std::env::args().nth(2) {
+        let knots: usize = knots.parse()?;
+        if knots < 2 {
+            return Err("a spline needs at least two knots".into());
+        }
+        autoquant::registry::register(autoquant::registry::spline(knots).with_name("spline"));
+    }
+
     let mut handles = Vec::new();
     handles.push(generate_plot(Diagram::Cdf, 0));
     handles.push(generate_plot(Diagram::Cdf, 1));
@@ -41,78 +54,132 @@ fn generate_plot(diagram: Diagram, color_index: usize) -> JoinHandle<()> {
         use autoquant::plot::plot_histogram;
         use std::env;
         let args: Vec<_> = env::args().collect();
-        if args.len() != 2 {
-            println!("Usage: {} <file>", args[0]);
+        if !(2..=3).contains(&args.len()) {
+            println!("Usage: {} <file> [spline knots]", args[0]);
             std::process::exit(2);
         }
         let file = &args[1];
         let image = rawloader::decode_file(file).unwrap();
         //dbg!(&image.data);
         let len = image.width * image.height / 4;
-        let dist = create_distribution(&image, len, color_index);
-        let full_dist = create_distribution(&image, len, color_index);
+        let (dist, normalization) = create_distribution(&image, len, color_index);
+        let (full_dist, _) = create_distribution(&image, len, color_index);
+        let config = autoquant::models::FitConfig::default();
+        config.error.metric.validate_unit(ErrorUnit::RawDn)?;
         match diagram {
             Diagram::Cdf => {
-                let functions = autoquant::fit_functions(dist.clone(), 40);
+                let functions = autoquant::fit_functions(dist.clone(), 40, &config)?;
 
                 //let models =
                 //    autoquant::fit_distributions(data.as_slice(), autoquant::fit_functions().as_slice());
-                for fit in functions.iter().take(4) {
+                for (fit, report) in functions.iter() {
                     let error = autoquant::distribution_error(&full_dist, fit.as_ref(), 40);
-                    println!("{} Error: {}", fit.name(), error);
+                    let raw =
+                        config
+                            .error
+                            .metric
+                            .convert(error, ErrorUnit::RawDn, &normalization)?;
+                    println!("{} Error: {} ({} DN, {:?})", fit.name(), error, raw, report);
                 }
                 let fits = functions
                     .iter()
-                    .take(4)
-                    .map(|fit| fit.as_ref() as &dyn autoquant::FitFn)
+                    .map(|(fit, _)| fit.as_ref() as &dyn autoquant::FitFn)
                     .collect::<Vec<_>>();
 
                 return plot_histogram(&dist, &fits, color);
             }
             Diagram::ErrorDistribution => {
-                let errors = autoquant::calculate_error_functions(&dist, &full_dist);
+                let values: Vec<f64> = channel_codes(&image, color_index)
+                    .into_iter()
+                    .map(f64::from)
+                    .collect();
+                let split = autoquant::crossval::train_test_split(
+                    &values,
+                    0.5,
+                    SplitStrategy::Blocks {
+                        width: image.width / 2,
+                        block: 64,
+                        seed: 0,
+                    },
+                )?;
+                let errors =
+                    autoquant::calculate_error_functions(&split.train, &split.test, &config)?;
                 println!("Errors: {:#?}", errors);
+                for (name, curve) in errors.0.iter().zip(&errors.1) {
+                    let raw = curve
+                        .iter()
+                        .map(|&(_, e)| {
+                            config
+                                .error
+                                .metric
+                                .convert(e, ErrorUnit::RawDn, &split.normalization)
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    println!("{} error in raw DN: {:?}", name, raw);
+                }
                 return plot_errors(&errors.1, &errors.0, color);
             }
             Diagram::PlotChannels => {
-                let red = create_distribution(&image, 100, 0);
-                let green = create_distribution(&image, 100, 1);
-                let blue = create_distribution(&image, 100, 3);
+                let (red, _) = create_distribution(&image, 100, 0);
+                let (green, _) = create_distribution(&image, 100, 1);
+                let (blue, _) = create_distribution(&image, 100, 3);
                 return plot_channels(&[&red, &green, &blue]);
             }
             Diagram::CombinedErrorFunction => {
-                let red = create_distribution(&image, len, 0);
-                let green = create_distribution(&image, len, 1);
-                let blue = create_distribution(&image, len, 3);
-                let fit_red = autoquant::calculate_error_function(&red, 0, &red);
-                let fit_green = autoquant::calculate_error_function(&green, 0, &green);
-                let fit_blue = autoquant::calculate_error_function(&blue, 0, &blue);
-                let red_error: ErrorFunction<10> =
-                    autoquant::packing::ErrorFunction::new(fit_red.as_slice());
-                let green_error: ErrorFunction<10> =
-                    autoquant::packing::ErrorFunction::new(fit_green.as_slice());
-                let blue_error: ErrorFunction<10> =
-                    autoquant::packing::ErrorFunction::new(fit_blue.as_slice());
-                let merged: ErrorFunction<24> =
-                    autoquant::packing::merge_error_functions(&red_error, &green_error);
-                println!("Merged: {:?}", merged);
-                let merged: ErrorFunction<32> =
-                    autoquant::packing::merge_error_functions(&merged, &blue_error);
-                println!("Merged: {:?}", merged);
-                let red_bits = merged.bits.iter().map(|x| x[0]).collect::<Vec<_>>();
-                let green_bits = merged.bits.iter().map(|x| x[1]).collect::<Vec<_>>();
-                let blue_bits = merged.bits.iter().map(|x| x[2]).collect::<Vec<_>>();
+                let (red, _) = create_distribution(&image, len, 0);
+                let (green, _) = create_distribution(&image, len, 1);
+                let (blue, _) = create_distribution(&image, len, 3);
+                let fit_red = autoquant::calculate_error_function(&red, "linear", &red, &config)?;
+                let fit_green =
+                    autoquant::calculate_error_function(&green, "linear", &green, &config)?;
+                let fit_blue =
+                    autoquant::calculate_error_function(&blue, "linear", &blue, &config)?;
+                let errors = [&fit_red, &fit_green, &fit_blue]
+                    .into_iter()
+                    .map(|fit| DynamicErrorFunction::from_sweep(fit))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                let [fit_red, fit_green, fit_blue] = [0, 1, 2].map(|c| errors[c].to_vec());
+                let allocations = autoquant::packing::allocate_bits(&errors, 31);
+                println!("Allocations: {:?}", allocations);
+                let bits = |channel: usize| {
+                    allocations
+                        .iter()
+                        .map(|a| a.bits[channel])
+                        .collect::<Vec<_>>()
+                };
+                let (red_bits, green_bits, blue_bits) = (bits(0), bits(1), bits(2));
                 println!("Red bits: {:?}", red_bits);
                 println!("Green bits: {:?}", green_bits);
                 println!("Blue bits: {:?}", blue_bits);
-                return plot_errors_with_bits(
-                    &[fit_red, fit_green, fit_blue][..],
+                plot_errors_with_bits(
+                    &[fit_red.clone(), fit_green.clone(), fit_blue.clone()][..],
                     &[
                         red_bits.as_slice(),
                         green_bits.as_slice(),
                         blue_bits.as_slice(),
                     ][..],
                     &["red".to_string(), "green".to_string(), "blue".to_string()][..],
+                )?;
+                let objectives = [
+                    ("total error", Objective::Sum),
+                    ("worst channel error", Objective::Max),
+                    ("L2-norm of the errors", Objective::Lp(2.)),
+                ];
+                let compared = objectives
+                    .iter()
+                    .map(|(name, objective)| {
+                        let allocations =
+                            allocate_bits_for(&errors, objective, &Constraints::new(), 31)?;
+                        let bits = (0..3)
+                            .map(|c| channel_bits(&allocations, c))
+                            .collect::<Vec<_>>();
+                        Ok((name.to_string(), bits))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                return plot_allocations(
+                    &[fit_red, fit_green, fit_blue][..],
+                    &compared,
+                    &["red".to_string(), "green".to_string(), "blue".to_string()][..],
                 );
             }
         }
@@ -120,10 +187,10 @@ fn generate_plot(diagram: Diagram, color_index: usize) -> JoinHandle<()> {
     std::thread::spawn(move || foo().unwrap())
 }
 
-fn create_distribution(image: &RawImage, samples: usize, channel: usize) -> Vec<(f64, f64)> {
+fn channel_codes(image: &RawImage, channel: usize) -> Vec<u16> {
     let rawloader::RawImageData::Integer(ref data) = image.data else {
-    panic!("Don't know how to process non-integer raw files");
-};
+        panic!("Don't know how to process non-integer raw files");
+    };
     let xoffset = channel % 2;
     let yoffset = channel / 2;
     let mut output = Vec::with_capacity(data.len() / 4);
@@ -133,21 +200,21 @@ fn create_distribution(image: &RawImage, samples: usize, channel: usize) -> Vec<
             output.push(data[index])
         }
     }
-    let data: Vec<f64> = output
-        .chunks(data.len() / (samples.min(data.len())))
-        .map(|x| x[0] as f64)
-        .collect();
-    let max = *data
-        .iter()
-        .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-        .unwrap();
-    let data: Vec<f64> = data.iter().map(|x| x / max).collect();
+    output
+}
+
+/// The normalized distribution of `channel` and the normalization it applied
+/// to the raw values.
+fn create_distribution(
+    image: &RawImage,
+    bins: usize,
+    channel: usize,
+) -> (Vec<(f64, f64)>, Normalization) {
+    let histogram = Histogram::from_codes(channel_codes(image, channel));
+    let histogram = histogram.rebin(histogram.bins().div_ceil(bins.max(1)));
     //let data = autoquant::generate_normal_distribution(3.0, 1.1, 1000);
     //data.iter_mut().for_each(|x| *x = x.abs());
-    let mut dist = autoquant::integrate_distribution(data);
-    autoquant::drop_duplicates(&mut dist);
-    let dist = autoquant::normalize_distribution(dist.as_slice());
-    dist
+    (histogram.distribution(), histogram.normalization())
 }
 
 enum Diagram {
@@ -0,0 +1,336 @@
+//! Error metrics for comparing the decoded values with the original input.
+
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Result};
+
+use crate::quantize::Quantizer;
+use crate::sum::Sum;
+use crate::Normalization;
+
+/// Smallest value used as the reference of relative and logarithmic errors,
+/// so black pixels do not produce infinite errors.
+const FLOOR: f64 = 1e-6;
+
+/// PSNR reported for a lossless quantization, so a zero error stays finite.
+pub const MAX_PSNR: f64 = 200.;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorMetric {
+    /// Root of the mean squared error in percent of the input range.
+    #[default]
+    Rmse,
+    /// Mean absolute error in percent of the input range.
+    Mae,
+    /// Largest absolute error in percent of the input range.
+    MaxAbs,
+    /// Root mean squared error relative to the input value, in percent.
+    Relative,
+    /// Peak signal to noise ratio in dB, higher is better. Capped at
+    /// [`MAX_PSNR`].
+    Psnr,
+    /// Root mean squared `log2(decoded / x)`, in stops.
+    Stops,
+}
+
+impl ErrorMetric {
+    /// The error of a single sample before aggregation.
+    pub fn residual(&self, x: f64, decoded: f64) -> f64 {
+        match self {
+            ErrorMetric::Rmse | ErrorMetric::Mae | ErrorMetric::MaxAbs | ErrorMetric::Psnr => {
+                decoded - x
+            }
+            ErrorMetric::Relative => (decoded - x) / x.abs().max(FLOOR),
+            ErrorMetric::Stops => (decoded.max(FLOOR) / x.max(FLOOR)).log2(),
+        }
+    }
+
+    /// Combines `(residual, weight)` pairs into the reported error.
+    pub fn aggregate(&self, samples: impl IntoIterator<Item = (f64, f64)>) -> f64 {
+        let mut total = Sum::new();
+        let mut weights = Sum::new();
+        let mut max = 0f64;
+        for (residual, weight) in samples {
+            weights.add(weight);
+            match self {
+                ErrorMetric::Mae => total.add(residual.abs() * weight),
+                ErrorMetric::MaxAbs if weight > 0. => max = max.max(residual.abs()),
+                ErrorMetric::MaxAbs => {}
+                _ => total.add(residual * residual * weight),
+            }
+        }
+        let weights = weights.sum();
+        let mean = if weights > 0. {
+            total.sum() / weights
+        } else {
+            0.
+        };
+        match self {
+            ErrorMetric::Rmse | ErrorMetric::Relative => mean.sqrt() * 100.,
+            ErrorMetric::Mae => mean * 100.,
+            ErrorMetric::MaxAbs => max * 100.,
+            ErrorMetric::Psnr => (-10. * mean.log10()).min(MAX_PSNR),
+            ErrorMetric::Stops => mean.sqrt(),
+        }
+    }
+
+    /// Checks that errors of this metric can be expressed in `unit`, so an
+    /// unsupported combination is rejected before any fitting.
+    pub fn validate_unit(&self, unit: ErrorUnit) -> Result<()> {
+        let absolute = matches!(
+            self,
+            ErrorMetric::Rmse | ErrorMetric::Mae | ErrorMetric::MaxAbs
+        );
+        match unit {
+            ErrorUnit::Normalized => {}
+            ErrorUnit::RawDn if absolute => {}
+            ErrorUnit::PercentOfFullScale { full_scale } if absolute => ensure!(
+                full_scale.is_finite() && full_scale > 0.,
+                "the full scale has to be positive, got {}",
+                full_scale
+            ),
+            ErrorUnit::Stops if *self == ErrorMetric::Stops => {}
+            ErrorUnit::Stops => bail!(
+                "{:?} errors can not be expressed in stops, use ErrorMetric::Stops",
+                self
+            ),
+            _ => bail!(
+                "{:?} errors are independent of the input scale, {:?} does not apply",
+                self,
+                unit
+            ),
+        }
+        Ok(())
+    }
+
+    /// Converts an error of this metric on inputs normalized with
+    /// `normalization` into `unit`, see [`ErrorMetric::validate_unit`].
+    pub fn convert(
+        &self,
+        error: f64,
+        unit: ErrorUnit,
+        normalization: &Normalization,
+    ) -> Result<f64> {
+        self.validate_unit(unit)?;
+        Ok(match unit {
+            ErrorUnit::RawDn => error / 100. * normalization.scale,
+            ErrorUnit::PercentOfFullScale { full_scale } => {
+                error * normalization.scale / full_scale
+            }
+            ErrorUnit::Normalized | ErrorUnit::Stops => error,
+        })
+    }
+
+    /// Maps an error of this metric to a cost which is lower for better
+    /// results. Fitting and bit allocation minimize costs.
+    pub fn cost(&self, error: f64) -> f64 {
+        match self {
+            ErrorMetric::Psnr => -error,
+            _ => error,
+        }
+    }
+
+    /// Combines the costs of two channels quantized independently.
+    ///
+    /// PSNR costs combine to the cost of the PSNR of the summed mean squared
+    /// errors, since summing decibels has no meaning.
+    pub fn combine(&self, first: f64, second: f64) -> f64 {
+        match self {
+            ErrorMetric::MaxAbs => first.max(second),
+            ErrorMetric::Psnr => {
+                let mse = |cost: f64| 10f64.powf(cost / 10.);
+                10. * (mse(first) + mse(second)).log10()
+            }
+            _ => first + second,
+        }
+    }
+}
+
+/// Unit an error of an [`ErrorMetric`] is reported in.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ErrorUnit {
+    /// The unit of the metric on normalized inputs, e.g. percent of the
+    /// channel maximum for [`ErrorMetric::Rmse`].
+    #[default]
+    Normalized,
+    /// Raw digital numbers of the sensor.
+    RawDn,
+    /// Percent of the full scale of the sensor, e.g. its white level.
+    PercentOfFullScale { full_scale: f64 },
+    /// Stops relative to the value, only available for
+    /// [`ErrorMetric::Stops`].
+    Stops,
+}
+
+/// Relative importance of the parts of the input domain.
+///
+/// Weights multiply the probability mass of each distribution point, so a
+/// weight of 2 counts an error twice as much as one with weight 1.
+#[derive(Clone, Default)]
+pub enum Weighting {
+    #[default]
+    Uniform,
+    /// Weight as a function of the normalized input value.
+    Function(Arc<dyn Fn(f64) -> f64 + Send + Sync>),
+    /// Weights at sorted normalized input values. Inputs between two points
+    /// use the weight of the lower one, inputs below the first point use its
+    /// weight, so the weights apply to any distribution of the same channel,
+    /// e.g. both sides of a train/test split.
+    Samples(Arc<[(f64, f64)]>),
+}
+
+impl Weighting {
+    pub fn function(weight: impl Fn(f64) -> f64 + Send + Sync + 'static) -> Self {
+        Weighting::Function(Arc::new(weight))
+    }
+
+    /// Weights from `(x, weight)` pairs in any order.
+    pub fn samples(weights: impl IntoIterator<Item = (f64, f64)>) -> Self {
+        let mut weights: Vec<_> = weights.into_iter().collect();
+        weights.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Weighting::Samples(weights.into())
+    }
+
+    /// Weight of the normalized input `x`.
+    pub fn weight(&self, x: f64) -> f64 {
+        match self {
+            Weighting::Uniform => 1.,
+            Weighting::Function(weight) => weight(x),
+            Weighting::Samples(weights) => {
+                let index = weights.partition_point(|&(x_, _)| x_ <= x);
+                weights
+                    .get(index.saturating_sub(1))
+                    .map_or(1., |&(_, weight)| weight)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Weighting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Weighting::Uniform => write!(f, "Uniform"),
+            Weighting::Function(_) => write!(f, "Function(..)"),
+            Weighting::Samples(weights) => write!(f, "Samples({} weights)", weights.len()),
+        }
+    }
+}
+
+/// How [`crate::distribution_error_with`] measures the quantization error.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorOptions {
+    pub metric: ErrorMetric,
+    pub weighting: Weighting,
+    pub quantizer: Quantizer,
+}
+
+impl ErrorOptions {
+    pub fn with_metric(mut self, metric: ErrorMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+    pub fn with_weighting(mut self, weighting: Weighting) -> Self {
+        self.weighting = weighting;
+        self
+    }
+    pub fn with_quantizer(mut self, quantizer: Quantizer) -> Self {
+        self.quantizer = quantizer;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{distribution_error_with, SimpleFitFn};
+
+    #[test]
+    fn weighting() {
+        let dist: Vec<_> = (1..=100).map(|i| (i as f64 / 100., i as f64)).collect();
+        let identity = SimpleFitFn {
+            function: |x: f64| x,
+            inverse: |x: f64| x,
+            name: "identity",
+        };
+        let uniform = ErrorOptions::default();
+        let shadows =
+            uniform
+                .clone()
+                .with_weighting(Weighting::function(|x| if x < 0.5 { 1. } else { 0. }));
+        let per_sample = uniform.clone().with_weighting(Weighting::samples(
+            dist.iter()
+                .rev()
+                .map(|&(x, _)| (x, if x < 0.5 { 1. } else { 0. })),
+        ));
+        let error = |options| distribution_error_with(&dist, &identity, 4, options);
+        assert_ne!(error(&uniform), error(&shadows));
+        assert_eq!(error(&shadows), error(&per_sample));
+
+        let weighting = Weighting::samples([(0.5, 2.), (0.25, 3.)]);
+        assert_eq!(weighting.weight(0.1), 3.);
+        assert_eq!(weighting.weight(0.25), 3.);
+        assert_eq!(weighting.weight(0.4), 3.);
+        assert_eq!(weighting.weight(0.9), 2.);
+        assert_eq!(Weighting::samples([]).weight(0.5), 1.);
+    }
+
+    #[test]
+    fn metrics() {
+        let samples = [(0.3, 1.), (-0.4, 1.), (0., 2.), (1., 0.)];
+        let aggregate = |metric: ErrorMetric| metric.aggregate(samples);
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        // mean squared residual 0.0625, mean absolute residual 0.175
+        assert!(close(aggregate(ErrorMetric::Rmse), 25.));
+        assert!(close(aggregate(ErrorMetric::Mae), 17.5));
+        assert!(close(aggregate(ErrorMetric::MaxAbs), 40.));
+        assert!(close(aggregate(ErrorMetric::Relative), 25.));
+        assert!(close(
+            aggregate(ErrorMetric::Psnr),
+            -10. * 0.0625f64.log10()
+        ));
+        assert!(close(aggregate(ErrorMetric::Stops), 0.25));
+
+        assert!(close(ErrorMetric::Relative.residual(0.5, 0.6), 0.2));
+        assert!(close(ErrorMetric::Stops.residual(0.25, 0.5), 1.));
+        assert!(close(ErrorMetric::Rmse.residual(0.25, 0.5), 0.25));
+
+        let psnr = ErrorMetric::Psnr;
+        assert_eq!(psnr.aggregate([(0., 1.)]), MAX_PSNR);
+        // two channels of 10 dB sum to a mean squared error of 0.2
+        let combined = psnr.combine(psnr.cost(10.), psnr.cost(10.));
+        assert!(close(combined, psnr.cost(-10. * 0.2f64.log10())));
+        assert_eq!(ErrorMetric::MaxAbs.combine(1., 2.), 2.);
+        assert_eq!(ErrorMetric::Rmse.combine(1., 2.), 3.);
+    }
+
+    #[test]
+    fn units() {
+        let normalization = Normalization::new(512., 16383.);
+        let rmse = ErrorMetric::Rmse;
+        assert_eq!(
+            rmse.convert(1., ErrorUnit::RawDn, &normalization).unwrap(),
+            163.83
+        );
+        let full_scale = ErrorUnit::PercentOfFullScale {
+            full_scale: 2. * 16383.,
+        };
+        assert_eq!(rmse.convert(1., full_scale, &normalization).unwrap(), 0.5);
+        assert!(rmse.convert(1., ErrorUnit::Stops, &normalization).is_err());
+        assert!(ErrorMetric::Relative
+            .convert(1., ErrorUnit::RawDn, &normalization)
+            .is_err());
+        assert!(ErrorMetric::Psnr.validate_unit(full_scale).is_err());
+        assert!(ErrorMetric::Psnr
+            .validate_unit(ErrorUnit::Normalized)
+            .is_ok());
+        let zero = ErrorUnit::PercentOfFullScale { full_scale: 0. };
+        assert!(rmse.validate_unit(zero).is_err());
+        assert_eq!(
+            ErrorMetric::Stops
+                .convert(0.1, ErrorUnit::Stops, &normalization)
+                .unwrap(),
+            0.1
+        );
+    }
+}
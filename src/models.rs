@@ -1,17 +1,61 @@
-use crate::{distribution_error, CreateFitFn};
+use crate::metric::{ErrorMetric, ErrorOptions};
+use crate::validate::{validate, validate_with, ValidationReport};
+use crate::{distribution_error_with, Bound, CreateFitFn};
 
 use super::FitFn;
 
 use argmin::core::observers::{ObserverMode, SlogLogger};
+use argmin::core::{Executor, Gradient, State, TerminationReason};
+use argmin::solver::linesearch::{condition::ArmijoCondition, BacktrackingLineSearch};
 use argmin::solver::neldermead::NelderMead;
+use argmin::solver::particleswarm::ParticleSwarm;
+use argmin::solver::quasinewton::LBFGS;
+use argmin::solver::simulatedannealing::{Anneal, SimulatedAnnealing};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_xoshiro::Xoshiro256PlusPlus;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use super::Dist;
 
+use anyhow::{ensure, Context};
 use argmin::core::{CostFunction, Error};
-struct Fit<T: CreateFitFn>(Dist, u64, std::marker::PhantomData<T>);
+
+/// Cost added to parameters which fail validation while repairing a fit.
+const INVALID_PENALTY: f64 = 1e6;
+/// Number of points checked per cost evaluation while repairing a fit.
+const PENALTY_SAMPLES: usize = 64;
+
+struct Fit<T: CreateFitFn> {
+    dist: Dist,
+    quantization: u64,
+    error: ErrorOptions,
+    evaluations: Arc<AtomicU64>,
+    /// Round trip tolerance of the validity penalty, if it is enabled.
+    penalty: Option<f64>,
+    /// Source of the random steps of simulated annealing.
+    rng: Mutex<StdRng>,
+    model: std::marker::PhantomData<T>,
+}
 impl<T: CreateFitFn> Fit<T> {
-    pub fn new(dist: Dist, quantization: u64) -> Self {
-        Self(dist, quantization, std::marker::PhantomData)
+    pub fn new(
+        dist: Dist,
+        quantization: u64,
+        error: ErrorOptions,
+        evaluations: Arc<AtomicU64>,
+        penalty: Option<f64>,
+        rng: StdRng,
+    ) -> Self {
+        Self {
+            dist,
+            quantization,
+            error,
+            evaluations,
+            penalty,
+            rng: Mutex::new(rng),
+            model: std::marker::PhantomData,
+        }
     }
 }
 
@@ -20,57 +64,465 @@ impl<T: CreateFitFn> CostFunction for Fit<T> {
     type Output = f64;
 
     fn cost(&self, param: &Self::Param) -> Result<Self::Output, Error> {
-        let log = T::new(param.clone());
-        let quantization = self.1;
-        let data = &self.0;
-        let sum = distribution_error(data, log, quantization);
+        self.evaluations.fetch_add(1, Ordering::Relaxed);
+        let log = T::new(constrain::<T>(param));
+        let error = distribution_error_with(&self.dist, &log, self.quantization, &self.error);
+        let mut sum = self.error.metric.cost(error);
+        if let Some(tolerance) = self.penalty {
+            if !validate_with(&log, PENALTY_SAMPLES).is_valid(tolerance) {
+                sum += INVALID_PENALTY;
+            }
+        }
         Ok(sum)
     }
 }
 
+impl<T: CreateFitFn> Gradient for Fit<T> {
+    type Param = Vec<f64>;
+    type Gradient = Vec<f64>;
+
+    /// Central differences, the quantized cost is piecewise constant so the
+    /// step is kept fairly large.
+    fn gradient(&self, param: &Self::Param) -> Result<Self::Gradient, Error> {
+        (0..param.len())
+            .map(|i| {
+                let h = 1e-4 * param[i].abs().max(1.);
+                let mut forward = param.clone();
+                forward[i] += h;
+                let mut backward = param.clone();
+                backward[i] -= h;
+                Ok((self.cost(&forward)? - self.cost(&backward)?) / (2. * h))
+            })
+            .collect()
+    }
+}
+
+impl<T: CreateFitFn> Anneal for Fit<T> {
+    type Param = Vec<f64>;
+    type Output = Vec<f64>;
+    type Float = f64;
+
+    fn anneal(&self, param: &Self::Param, extent: Self::Float) -> Result<Self::Output, Error> {
+        let mut rng = self.rng.lock().unwrap();
+        Ok(param
+            .iter()
+            .map(|p| p + rng.gen_range(-0.1..0.1) * p.abs().max(1.) * extent)
+            .collect())
+    }
+}
+
+/// Maps the unconstrained solver parameters into the bounds of `T`.
+fn constrain<T: CreateFitFn>(param: &[f64]) -> Vec<f64> {
+    param
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| T::bound(i).transform(p))
+        .collect()
+}
+
+fn unconstrain<T: CreateFitFn>(param: &[f64]) -> Vec<f64> {
+    param
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| T::bound(i).inverse_transform(p))
+        .collect()
+}
+
+/// What to do with fitted models that fail [`validate`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValidationPolicy {
+    /// Return the fit without checking it.
+    Ignore,
+    /// Return an error for fits which are not valid within the round trip
+    /// `tolerance`.
+    Reject { tolerance: f64 },
+    /// Refit invalid models with a penalty on invalid parameters and return an
+    /// error if that does not yield a valid fit either.
+    Repair { tolerance: f64 },
+}
+
+/// Optimization algorithm used to fit the model parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Solver {
+    NelderMead,
+    ParticleSwarm {
+        particles: usize,
+    },
+    SimulatedAnnealing {
+        temperature: f64,
+    },
+    /// L-BFGS on numeric gradients with a history of `memory` steps.
+    Lbfgs {
+        memory: usize,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct FitConfig {
+    pub solver: Solver,
+    pub max_iters: u64,
+    /// Convergence tolerance, interpreted by the solver: the standard deviation
+    /// of the simplex for Nelder-Mead, the gradient norm for L-BFGS. Particle
+    /// swarm and simulated annealing ignore it and always run for
+    /// `max_iters` iterations.
+    pub tolerance: f64,
+    /// Starting parameters, the initial simplex of the model is used if unset.
+    pub initial: Option<Vec<f64>>,
+    pub validation: ValidationPolicy,
+    /// The error minimized by the solver.
+    pub error: ErrorOptions,
+    /// Seed of simulated annealing, a random seed is used if unset. Particle
+    /// swarm always draws its particles from the thread rng of argmin.
+    pub seed: Option<u64>,
+}
+
+impl Default for FitConfig {
+    fn default() -> Self {
+        Self {
+            solver: Solver::NelderMead,
+            max_iters: 1000,
+            tolerance: f64::EPSILON,
+            initial: None,
+            validation: ValidationPolicy::Ignore,
+            error: ErrorOptions::default(),
+            seed: None,
+        }
+    }
+}
+
+impl FitConfig {
+    pub fn with_solver(mut self, solver: Solver) -> Self {
+        self.solver = solver;
+        self
+    }
+    pub fn with_max_iters(mut self, max_iters: u64) -> Self {
+        self.max_iters = max_iters;
+        self
+    }
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+    pub fn with_initial(mut self, initial: Vec<f64>) -> Self {
+        self.initial = Some(initial);
+        self
+    }
+    pub fn with_validation(mut self, validation: ValidationPolicy) -> Self {
+        self.validation = validation;
+        self
+    }
+    pub fn with_error(mut self, error: ErrorOptions) -> Self {
+        self.error = error;
+        self
+    }
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    /// The simplex to start from, built around `initial` if it is set. Each
+    /// vertex steps away from `initial` towards the inside of `bound`, so no
+    /// vertex collapses onto the start when it lies on a bound.
+    fn simplex(
+        &self,
+        initial_simplex: Vec<Vec<f64>>,
+        bound: impl Fn(usize) -> Bound,
+    ) -> Vec<Vec<f64>> {
+        let Some(start) = &self.initial else {
+            return initial_simplex;
+        };
+        let mut simplex = vec![start.clone()];
+        for i in 0..start.len() {
+            let mut vertex = start.clone();
+            let step = if start[i] == 0. { 0.1 } else { 0.1 * start[i] };
+            vertex[i] += if bound(i).contains(start[i] + step) {
+                step
+            } else {
+                -step
+            };
+            simplex.push(vertex);
+        }
+        simplex
+    }
+}
+
+/// Why a fit stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    /// The model was constructed directly instead of being optimized.
+    Direct,
+    MaxIters,
+    TargetCost,
+    /// The convergence criterion of the solver was met, see
+    /// [`FitConfig::tolerance`].
+    Converged,
+    /// Simulated annealing did not improve for too long.
+    Stalled,
+    Aborted,
+    /// The solver returned without a termination reason.
+    NotTerminated,
+}
+
+impl From<TerminationReason> for Termination {
+    fn from(reason: TerminationReason) -> Self {
+        match reason {
+            TerminationReason::NotTerminated => Termination::NotTerminated,
+            TerminationReason::MaxItersReached => Termination::MaxIters,
+            TerminationReason::TargetCostReached => Termination::TargetCost,
+            TerminationReason::TargetPrecisionReached
+            | TerminationReason::NoChangeInCost
+            | TerminationReason::LineSearchConditionMet
+            | TerminationReason::TargetToleranceReached => Termination::Converged,
+            TerminationReason::AcceptedStallIterExceeded
+            | TerminationReason::BestStallIterExceeded => Termination::Stalled,
+            TerminationReason::Aborted => Termination::Aborted,
+        }
+    }
+}
+
+/// Diagnostics of a single fit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitReport {
+    /// Cost of the returned parameters on the training distribution, see
+    /// [`crate::metric::ErrorMetric::cost`].
+    pub best_cost: f64,
+    /// Solver iterations, summed over both runs if the fit was repaired.
+    pub iterations: u64,
+    /// Number of evaluations of the cost function, including the ones needed
+    /// for numeric gradients.
+    pub cost_evaluations: u64,
+    /// Why the solver stopped.
+    pub termination: Termination,
+    pub duration: Duration,
+    /// Checks of the returned model, unless validation was disabled.
+    pub validation: Option<ValidationReport>,
+}
+
+impl FitReport {
+    /// Report for a model which is constructed directly instead of being
+    /// optimized by a solver.
+    pub fn direct(
+        fit: &dyn FitFn,
+        dist: &[(f64, f64)],
+        quantization: u64,
+        error: &ErrorOptions,
+        start: Instant,
+    ) -> Self {
+        let best_error = distribution_error_with(dist, fit, quantization, error);
+        Self::from_error(best_error, error.metric, start)
+    }
+
+    /// Report for a model which is constructed directly and scored with its
+    /// own error function, see [`crate::registry::ModelDescriptor::with_error`].
+    pub fn from_error(error: f64, metric: ErrorMetric, start: Instant) -> Self {
+        Self {
+            best_cost: metric.cost(error),
+            iterations: 0,
+            cost_evaluations: 1,
+            termination: Termination::Direct,
+            duration: start.elapsed(),
+            validation: None,
+        }
+    }
+
+    fn new<S: State<Float = f64>>(state: &S, evaluations: &AtomicU64, start: Instant) -> Self {
+        Self {
+            best_cost: state.get_best_cost(),
+            iterations: state.get_iter(),
+            cost_evaluations: evaluations.load(Ordering::Relaxed),
+            termination: state.get_termination_reason().into(),
+            duration: start.elapsed(),
+            validation: None,
+        }
+    }
+}
+
+/// Fits the parameters of `T` to `dist` with the solver selected in `config`.
+/// Unless `config` provides starting parameters the search starts from
+/// `initial_simplex`. The solver searches an unconstrained space which is
+/// mapped into [`CreateFitFn::bound`], so the returned parameters always lie
+/// inside the declared bounds.
+pub fn fit<T: CreateFitFn>(
+    dist: Dist,
+    quantization: u64,
+    initial_simplex: Vec<Vec<f64>>,
+    config: &FitConfig,
+) -> anyhow::Result<(T, FitReport)> {
+    let start_time = Instant::now();
+    let simplex: Vec<_> = config
+        .simplex(initial_simplex, T::bound)
+        .iter()
+        .map(|vertex| unconstrain::<T>(vertex))
+        .collect();
+    //log::debug!("dist: {:?}", dist);
+    let evaluations = Arc::new(AtomicU64::new(0));
+    let mut rng = config.rng();
+    let repair = match config.validation {
+        ValidationPolicy::Repair { tolerance } => Some((dist.clone(), tolerance)),
+        _ => None,
+    };
+    let problem: Fit<T> = Fit::new(
+        dist,
+        quantization,
+        config.error.clone(),
+        evaluations.clone(),
+        None,
+        StdRng::seed_from_u64(rng.gen()),
+    );
+    let (params, mut report) = solve(problem, simplex.clone(), config, &mut rng, start_time)?;
+    let mut model = T::new(params);
+
+    let tolerance = match config.validation {
+        ValidationPolicy::Ignore => return Ok((model, report)),
+        ValidationPolicy::Reject { tolerance } | ValidationPolicy::Repair { tolerance } => {
+            tolerance
+        }
+    };
+    let mut validation = validate(&model);
+    if let (false, Some((dist, tolerance))) = (validation.is_valid(tolerance), repair) {
+        log::debug!("repairing invalid {} fit: {:?}", model.name(), validation);
+        let problem: Fit<T> = Fit::new(
+            dist,
+            quantization,
+            config.error.clone(),
+            evaluations,
+            Some(tolerance),
+            StdRng::seed_from_u64(rng.gen()),
+        );
+        let (params, repaired) = solve(problem, simplex, config, &mut rng, start_time)?;
+        model = T::new(params);
+        report = FitReport {
+            iterations: report.iterations + repaired.iterations,
+            ..repaired
+        };
+        validation = validate(&model);
+    }
+    ensure!(
+        validation.is_valid(tolerance),
+        "fitted {} model is invalid: {:?}",
+        model.name(),
+        validation
+    );
+    report.validation = Some(validation);
+    Ok((model, report))
+}
+
+/// Runs the solver selected in `config` on `problem` and returns the best
+/// parameters, mapped into the bounds of `T`.
+fn solve<T: CreateFitFn>(
+    problem: Fit<T>,
+    simplex: Vec<Vec<f64>>,
+    config: &FitConfig,
+    rng: &mut StdRng,
+    start_time: Instant,
+) -> anyhow::Result<(Vec<f64>, FitReport)> {
+    let start = simplex[0].clone();
+    let evaluations = problem.evaluations.clone();
+    let max_iters = config.max_iters;
+    let (params, report) = match config.solver {
+        Solver::NelderMead => {
+            let solver: NelderMead<Vec<f64>, f64> =
+                NelderMead::new(simplex).with_sd_tolerance(config.tolerance)?;
+            let executor =
+                Executor::new(problem, solver).configure(|state| state.max_iters(max_iters)); //.add_observer(SlogLogger::term(), ObserverMode::Every(200));
+            let res = executor.run()?;
+            let state = res.state();
+            (
+                state.get_best_param().cloned(),
+                FitReport::new(state, &evaluations, start_time),
+            )
+        }
+        Solver::ParticleSwarm { particles } => {
+            let radius: Vec<f64> = start.iter().map(|p| p.abs().max(1.)).collect();
+            let lower = start.iter().zip(&radius).map(|(p, r)| p - r).collect();
+            let upper = start.iter().zip(&radius).map(|(p, r)| p + r).collect();
+            let solver = ParticleSwarm::new((lower, upper), particles);
+            let executor =
+                Executor::new(problem, solver).configure(|state| state.max_iters(max_iters));
+            let res = executor.run()?;
+            let state = res.state();
+            (
+                state
+                    .get_best_param()
+                    .map(|particle| particle.position.clone()),
+                FitReport::new(state, &evaluations, start_time),
+            )
+        }
+        Solver::SimulatedAnnealing { temperature } => {
+            // argmin serializes the solver state, so its rng has to be serializable
+            let rng = Xoshiro256PlusPlus::seed_from_u64(rng.gen());
+            let solver = SimulatedAnnealing::new_with_rng(temperature, rng)?;
+            let executor = Executor::new(problem, solver)
+                .configure(|state| state.param(start).max_iters(max_iters));
+            let res = executor.run()?;
+            let state = res.state();
+            (
+                state.get_best_param().cloned(),
+                FitReport::new(state, &evaluations, start_time),
+            )
+        }
+        Solver::Lbfgs { memory } => {
+            // the quantized cost is piecewise constant, so the numeric gradient
+            // is not always a descent direction, which More-Thuente rejects
+            let linesearch = BacktrackingLineSearch::new(ArmijoCondition::new(1e-4)?);
+            let solver = LBFGS::new(linesearch, memory).with_tolerance_grad(config.tolerance)?;
+            let executor = Executor::new(problem, solver)
+                .configure(|state| state.param(start).max_iters(max_iters));
+            let res = executor.run()?;
+            let state = res.state();
+            (
+                state.get_best_param().cloned(),
+                FitReport::new(state, &evaluations, start_time),
+            )
+        }
+    };
+    let params = constrain::<T>(&params.context("solver did not find any parameters")?);
+    //log::debug!("params: {:?}", params);
+    Ok((params, report))
+}
+
 #[derive(Debug)]
 pub struct OptimizedLog(Vec<f64>);
 
 impl OptimizedLog {
-    pub fn new(dist: Dist, quantization: u64) -> Self {
-        let params = vec![
+    pub fn new(dist: Dist, quantization: u64) -> anyhow::Result<(Self, FitReport)> {
+        fit(
+            dist,
+            quantization,
+            Self::initial_simplex(),
+            &FitConfig::default(),
+        )
+    }
+
+    pub fn initial_simplex() -> Vec<Vec<f64>> {
+        vec![
             vec![0.01, 0.3, 0.5, 4.],
             vec![0.009, 0.3, 0.5, 4.],
             vec![0.01, 0.4, 0.5, 4.],
             vec![0.01, 0.3, 0.6, 4.],
             vec![0.01, 0.3, 0.5, 5.],
-        ];
-        let nm: NelderMead<Vec<f64>, f64> = NelderMead::new(params);
-
-        //log::debug!("dist: {:?}", dist);
-        //println!("dist: {:?}", dist);
-        let fit: Fit<OptimizedLog> = Fit::new(dist, quantization);
-        let executor =
-            argmin::core::Executor::new(fit, nm).configure(|state| state.max_iters(1000)); //.add_observer(SlogLogger::term(), ObserverMode::Every(200));
-        let res = executor.run().unwrap();
-        let params = res.state().best_param.clone().unwrap();
-        //println!("Result: {:?}", res.state().best_cost);
-        //println!("params: {:?}", params);
-        //log::debug!("params: {:?}", params);
-        Self(params)
-    }
-
-    pub fn parameters(&self) -> &[f64] {
-        &self.0
+        ]
     }
 }
 
 impl FitFn for OptimizedLog {
     fn function(&self, x: f64) -> f64 {
         let a = self.0[0];
-        let b = self.0[1].max(0.1);
+        let b = self.0[1];
         let c = self.0[2];
         let d = self.0[3];
         ((x + a) * d).ln() * b + c
     }
     fn inverse(&self, x: f64) -> f64 {
         let a = self.0[0];
-        let b = self.0[1].max(0.1);
+        let b = self.0[1];
         let c = self.0[2];
         let d = self.0[3];
         -(-c / b).exp() * (a * d * (c / b).exp() - (x / b).exp()) / d
@@ -78,43 +530,48 @@ impl FitFn for OptimizedLog {
     fn name(&self) -> &str {
         "log"
     }
+    fn parameters(&self) -> &[f64] {
+        &self.0
+    }
 }
 
 impl CreateFitFn for OptimizedLog {
     fn new(params: Vec<f64>) -> Self {
         Self(params)
     }
+    /// Keeps the argument of the logarithm positive on [0, 1] and the curve
+    /// from flattening out.
+    fn bound(index: usize) -> Bound {
+        match index {
+            0 | 3 => Bound::at_least(0.),
+            1 => Bound::at_least(0.1),
+            _ => Bound::UNBOUNDED,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct OptimizedPow(Vec<f64>);
 
 impl OptimizedPow {
-    pub fn new(dist: Dist, quantization: u64) -> Self {
-        let params = vec![
+    pub fn new(dist: Dist, quantization: u64) -> anyhow::Result<(Self, FitReport)> {
+        fit(
+            dist,
+            quantization,
+            Self::initial_simplex(),
+            &FitConfig::default(),
+        )
+    }
+
+    pub fn initial_simplex() -> Vec<Vec<f64>> {
+        vec![
             vec![0.0, 1.0, 0.0, 1., 1.0],
             vec![0.1, 1.0, 0.0, 1., 1.0],
             vec![0.0, 1.1, 0.0, 1., 1.0],
             vec![0.0, 1.0, 0.1, 1., 1.0],
             vec![0.0, 1.0, 0.0, 1.1, 1.0],
             vec![0.0, 1.0, 0.0, 1., 0.5],
-        ];
-        let nm: NelderMead<Vec<f64>, f64> = NelderMead::new(params);
-
-        //log::debug!("dist: {:?}", dist);
-        //println!("dist: {:?}", dist);
-        let fit: Fit<OptimizedPow> = Fit::new(dist, quantization);
-        let executor =
-            argmin::core::Executor::new(fit, nm).configure(|state| state.max_iters(1000)); //.add_observer(SlogLogger::term(), ObserverMode::Every(200));
-        let res = executor.run().unwrap();
-        let params = res.state().best_param.clone().unwrap();
-        //println!("Result: {:?}", res.state().best_cost);
-        //println!("params: {:?}", params);
-        //log::debug!("params: {:?}", params);
-        Self(params)
-    }
-    pub fn parameters(&self) -> &[f64] {
-        &self.0
+        ]
     }
 }
 
@@ -138,36 +595,40 @@ impl FitFn for OptimizedPow {
     fn name(&self) -> &str {
         "powf"
     }
+    fn parameters(&self) -> &[f64] {
+        &self.0
+    }
 }
 
 impl CreateFitFn for OptimizedPow {
     fn new(params: Vec<f64>) -> Self {
         Self(params)
     }
+    /// Keeps the curve increasing. The black level offset `a` may be
+    /// positive, inputs below it are clamped to zero.
+    fn bound(index: usize) -> Bound {
+        match index {
+            1 | 3 | 4 => Bound::at_least(0.),
+            _ => Bound::UNBOUNDED,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct OptimizedLin(Vec<f64>);
 
 impl OptimizedLin {
-    pub fn new(dist: Dist, quantization: u64) -> Self {
-        let params = vec![vec![1.0, 0.0], vec![1.1, 0.0], vec![1.0, -0.1]];
-        let nm: NelderMead<Vec<f64>, f64> = NelderMead::new(params);
-
-        //log::debug!("dist: {:?}", dist);
-        //println!("dist: {:?}", dist);
-        let fit: Fit<OptimizedLin> = Fit::new(dist, quantization);
-        let executor =
-            argmin::core::Executor::new(fit, nm).configure(|state| state.max_iters(1000)); //.add_observer(SlogLogger::term(), ObserverMode::Every(200));
-        let res = executor.run().unwrap();
-        let params = res.state().best_param.clone().unwrap();
-        //println!("Result: {:?}", res.state().best_cost);
-        //println!("params: {:?}", params);
-        //log::debug!("params: {:?}", params);
-        Self(params)
+    pub fn new(dist: Dist, quantization: u64) -> anyhow::Result<(Self, FitReport)> {
+        fit(
+            dist,
+            quantization,
+            Self::initial_simplex(),
+            &FitConfig::default(),
+        )
     }
-    pub fn parameters(&self) -> &[f64] {
-        &self.0
+
+    pub fn initial_simplex() -> Vec<Vec<f64>> {
+        vec![vec![1.0, 0.0], vec![1.1, 0.0], vec![1.0, -0.1]]
     }
 }
 
@@ -185,6 +646,9 @@ impl FitFn for OptimizedLin {
     fn name(&self) -> &str {
         "linear"
     }
+    fn parameters(&self) -> &[f64] {
+        &self.0
+    }
 }
 
 impl CreateFitFn for OptimizedLin {
@@ -197,29 +661,22 @@ impl CreateFitFn for OptimizedLin {
 pub struct OptimizedExp(Vec<f64>);
 
 impl OptimizedExp {
-    pub fn new(dist: Dist, quantization: u64) -> Self {
-        let params = vec![
+    pub fn new(dist: Dist, quantization: u64) -> anyhow::Result<(Self, FitReport)> {
+        fit(
+            dist,
+            quantization,
+            Self::initial_simplex(),
+            &FitConfig::default(),
+        )
+    }
+
+    pub fn initial_simplex() -> Vec<Vec<f64>> {
+        vec![
             vec![1.0, 1., 0.0],
             vec![1.1, 1., 0.0],
             vec![1.0, 1.1, 0.0],
             vec![1.0, 1., 0.1],
-        ];
-        let nm: NelderMead<Vec<f64>, f64> = NelderMead::new(params);
-
-        //log::debug!("dist: {:?}", dist);
-        //println!("dist: {:?}", dist);
-        let fit: Fit<OptimizedExp> = Fit::new(dist, quantization);
-        let executor =
-            argmin::core::Executor::new(fit, nm).configure(|state| state.max_iters(1000)); //.add_observer(SlogLogger::term(), ObserverMode::Every(200));
-        let res = executor.run().unwrap();
-        let params = res.state().best_param.clone().unwrap();
-        //println!("Result: {:?}", res.state().best_cost);
-        //println!("params: {:?}", params);
-        //log::debug!("params: {:?}", params);
-        Self(params)
-    }
-    pub fn parameters(&self) -> &[f64] {
-        &self.0
+        ]
     }
 }
 
@@ -239,6 +696,9 @@ impl FitFn for OptimizedExp {
     fn name(&self) -> &str {
         "exp"
     }
+    fn parameters(&self) -> &[f64] {
+        &self.0
+    }
 }
 
 impl CreateFitFn for OptimizedExp {
@@ -246,3 +706,241 @@ impl CreateFitFn for OptimizedExp {
         Self(params)
     }
 }
+
+/// Monotone piecewise linear curve through equally spaced knots on [0, 1].
+///
+/// The first parameter is the value at `x = 0`, every following parameter is
+/// the non-negative increment to the next knot, so the curve is
+/// non-decreasing. The curve is extrapolated linearly outside of [0, 1].
+#[derive(Debug)]
+pub struct OptimizedSpline {
+    params: Vec<f64>,
+    knots: Vec<(f64, f64)>,
+}
+
+impl OptimizedSpline {
+    pub const DEFAULT_KNOTS: usize = 8;
+
+    pub fn new(dist: Dist, quantization: u64) -> anyhow::Result<(Self, FitReport)> {
+        Self::with_knots(dist, quantization, Self::DEFAULT_KNOTS)
+    }
+
+    pub fn with_knots(
+        dist: Dist,
+        quantization: u64,
+        knots: usize,
+    ) -> anyhow::Result<(Self, FitReport)> {
+        fit(
+            dist,
+            quantization,
+            Self::initial_simplex(knots),
+            &FitConfig::default(),
+        )
+    }
+
+    /// Simplex around the identity curve with `knots` knots.
+    pub fn initial_simplex(knots: usize) -> Vec<Vec<f64>> {
+        assert!(knots >= 2, "a spline needs at least two knots");
+        let step = 1. / (knots - 1) as f64;
+        let mut start = vec![step; knots];
+        start[0] = 0.;
+        let mut params = vec![start.clone()];
+        for i in 0..knots {
+            let mut vertex = start.clone();
+            vertex[i] += 0.1 * step;
+            params.push(vertex);
+        }
+        params
+    }
+
+    pub fn knots(&self) -> &[(f64, f64)] {
+        &self.knots
+    }
+
+    fn segment(&self, index: usize) -> ((f64, f64), (f64, f64)) {
+        let index = index.min(self.knots.len() - 2);
+        (self.knots[index], self.knots[index + 1])
+    }
+}
+
+impl FitFn for OptimizedSpline {
+    fn function(&self, x: f64) -> f64 {
+        let index = self.knots.partition_point(|&(kx, _)| kx <= x);
+        let ((x0, y0), (x1, y1)) = self.segment(index.saturating_sub(1));
+        y0 + (x - x0) * (y1 - y0) / (x1 - x0)
+    }
+    fn inverse(&self, x: f64) -> f64 {
+        let index = self.knots.partition_point(|&(_, ky)| ky <= x);
+        let ((x0, y0), (x1, y1)) = self.segment(index.saturating_sub(1));
+        if y1 == y0 {
+            return x0;
+        }
+        x0 + (x - y0) * (x1 - x0) / (y1 - y0)
+    }
+    fn name(&self) -> &str {
+        "spline"
+    }
+    fn parameters(&self) -> &[f64] {
+        &self.params
+    }
+}
+
+impl CreateFitFn for OptimizedSpline {
+    fn new(params: Vec<f64>) -> Self {
+        assert!(
+            params.len() >= 2,
+            "a spline needs at least two knots, got {}",
+            params.len()
+        );
+        let step = 1. / (params.len() - 1) as f64;
+        let mut y = 0.;
+        let knots = params
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| {
+                y = if i == 0 { p } else { y + p };
+                (i as f64 * step, y)
+            })
+            .collect();
+        Self { params, knots }
+    }
+    fn bound(index: usize) -> Bound {
+        match index {
+            0 => Bound::UNBOUNDED,
+            _ => Bound::at_least(0.),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Identity whose inverse is only exact on the codes of 4 levels unless
+    /// the parameter is zero, while the error prefers a parameter of one.
+    struct Wobbly(Vec<f64>);
+
+    impl FitFn for Wobbly {
+        fn function(&self, x: f64) -> f64 {
+            x
+        }
+        fn inverse(&self, x: f64) -> f64 {
+            let p = self.0[0];
+            x + p * (4. * std::f64::consts::PI * x).sin() + 1e-3 * (p - 1.).powi(2) * x
+        }
+        fn name(&self) -> &str {
+            "wobbly"
+        }
+    }
+
+    impl CreateFitFn for Wobbly {
+        fn new(params: Vec<f64>) -> Self {
+            Self(params)
+        }
+    }
+
+    #[test]
+    fn warm_start_on_bound() {
+        let config = FitConfig::default().with_initial(vec![0., 1., 0.]);
+        let bound = |i| match i {
+            0 => Bound::at_most(0.),
+            1 => Bound::between(0., 1.),
+            _ => Bound::UNBOUNDED,
+        };
+        let simplex = config.simplex(Vec::new(), bound);
+        assert_eq!(simplex[1], vec![-0.1, 1., 0.]);
+        assert_eq!(simplex[2], vec![0., 0.9, 0.]);
+        assert_eq!(simplex[3], vec![0., 1., 0.1]);
+        // every vertex stays distinct in the unconstrained space
+        let unconstrained: Vec<Vec<f64>> = simplex
+            .iter()
+            .map(|vertex| {
+                vertex
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &p)| bound(i).inverse_transform(p))
+                    .collect()
+            })
+            .collect();
+        for vertex in &unconstrained[1..] {
+            assert_ne!(vertex, &unconstrained[0]);
+        }
+    }
+
+    #[test]
+    fn reports() {
+        let dist: Vec<_> = (1..=64).map(|i| (i as f64 / 64., i as f64 / 64.)).collect();
+        let config = FitConfig::default().with_max_iters(10);
+        let simplex = vec![vec![1.], vec![0.]];
+
+        let (_, report) = fit::<Wobbly>(dist.clone(), 4, simplex.clone(), &config).unwrap();
+        assert_eq!(report.iterations, 10);
+        assert_eq!(report.termination, Termination::MaxIters);
+        assert!(report.cost_evaluations >= report.iterations);
+        assert_eq!(report.validation, None);
+
+        let reject = config
+            .clone()
+            .with_validation(ValidationPolicy::Reject { tolerance: 0.01 });
+        assert!(fit::<Wobbly>(dist.clone(), 4, simplex.clone(), &reject).is_err());
+
+        let repair = config.with_validation(ValidationPolicy::Repair { tolerance: 0.01 });
+        let (model, report) = fit::<Wobbly>(dist.clone(), 4, simplex, &repair).unwrap();
+        assert!(model.0[0].abs() < 0.01, "{:?}", model.0);
+        assert_eq!(report.iterations, 20);
+        assert!(report.validation.unwrap().is_valid(0.01));
+
+        let direct = FitReport::direct(&model, &dist, 4, &ErrorOptions::default(), Instant::now());
+        assert_eq!(direct.termination, Termination::Direct);
+        assert_eq!((direct.iterations, direct.cost_evaluations), (0, 1));
+    }
+
+    #[test]
+    fn solvers() {
+        let dist: Vec<_> = (1..=64).map(|i| (i as f64 / 64., i as f64 / 64.)).collect();
+        let identity = <OptimizedLin as CreateFitFn>::new(vec![1., 0.]);
+        let reference = crate::distribution_error(&dist, &identity, 16);
+        for solver in [
+            Solver::NelderMead,
+            Solver::ParticleSwarm { particles: 10 },
+            Solver::SimulatedAnnealing { temperature: 0.5 },
+            Solver::Lbfgs { memory: 5 },
+        ] {
+            let config = FitConfig::default()
+                .with_solver(solver)
+                .with_max_iters(50)
+                .with_seed(7);
+            let fit = || {
+                fit::<OptimizedLin>(dist.clone(), 16, OptimizedLin::initial_simplex(), &config)
+                    .unwrap()
+            };
+            let (model, report) = fit();
+            assert!(
+                report.best_cost <= 2. * reference,
+                "{:?}: {} > {}",
+                solver,
+                report.best_cost,
+                reference
+            );
+            assert!(model.function(0.5) > 0.25 && model.function(0.5) < 0.75);
+            if !matches!(solver, Solver::ParticleSwarm { .. }) {
+                assert_eq!(fit().0.parameters(), model.parameters(), "{:?}", solver);
+            }
+        }
+    }
+
+    #[test]
+    fn spline_inverse() {
+        let spline = <OptimizedSpline as CreateFitFn>::new(vec![0.1, 0.2, 0.3, 0.1, 0.4]);
+        for x in [-0.2, 0.0, 0.3, 0.5, 0.6, 0.9, 1.0, 1.2] {
+            let y = spline.function(x);
+            assert!((spline.inverse(y) - x).abs() < 1e-12, "{} -> {}", x, y);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at least two knots")]
+    fn spline_knots() {
+        <OptimizedSpline as CreateFitFn>::new(vec![0.1]);
+    }
+}
@@ -1,8 +1,11 @@
 use std::{
     borrow::Cow,
+    collections::BTreeMap,
     ops::{Deref, Index, IndexMut},
 };
 
+use anyhow::{ensure, Result};
+
 pub const BUCKET_SIZE: usize = 32;
 pub type Function = [f64; BUCKET_SIZE];
 
@@ -51,6 +54,29 @@ impl<'a, const N: usize> ErrorFunction<'a, N> {
             bits: Cow::Owned(core::array::from_fn(|x| vec![x])),
         }
     }
+    /// Error function of one statistic of bootstrapped error bands, e.g.
+    /// [`BandStatistic::Upper`](crate::bootstrap::BandStatistic::Upper) to
+    /// allocate bits for the pessimistic case. Fails unless the bands cover
+    /// the whole bit depths `0..N`, see [`whole_bits`].
+    #[cfg(feature = "fitting")]
+    pub fn from_bands(
+        bands: &[(u64, crate::bootstrap::ErrorBand)],
+        statistic: crate::bootstrap::BandStatistic,
+    ) -> Result<ErrorFunction<'static, N>> {
+        let errors = whole_bits(&crate::bootstrap::curve(bands, statistic))?;
+        ensure!(
+            errors.len() >= N,
+            "{} bit depths are needed, got {}",
+            N,
+            errors.len()
+        );
+        let mut errors = errors.into_iter();
+        Ok(ErrorFunction {
+            index: 0,
+            function: Cow::Owned(core::array::from_fn(|_| errors.next().unwrap())),
+            bits: Cow::Owned(core::array::from_fn(|x| vec![x])),
+        })
+    }
     pub fn empty() -> ErrorFunction<'static, N> {
         ErrorFunction {
             index: 0,
@@ -69,13 +95,24 @@ impl<'a, const N: usize> ErrorFunction<'a, N> {
         &mut self,
         first: &ErrorFunction<'a, A>,
         second: &ErrorFunction<'a, B>,
+    ) {
+        self.push_with(first, second, |a, b| a + b)
+    }
+
+    /// Like [`ErrorFunction::push`] but combines the errors of the two
+    /// functions with `combine` instead of adding them.
+    pub fn push_with<const A: usize, const B: usize>(
+        &mut self,
+        first: &ErrorFunction<'a, A>,
+        second: &ErrorFunction<'a, B>,
+        combine: impl Fn(f64, f64) -> f64,
     ) {
         // use dynamic programming to merge the error functions
         let mut min = f64::MAX;
         let mut first_bits = self.index;
         for i in 0..=self.index {
             //println!("{} {}", i, self.index - i);
-            let error = first[i] + second[self.index - i];
+            let error = combine(first[i], second[self.index - i]);
             if error < min {
                 min = error;
                 first_bits = i;
@@ -89,6 +126,172 @@ impl<'a, const N: usize> ErrorFunction<'a, N> {
     }
 }
 
+/// The errors of `(levels, error)` pairs indexed by bit count. Fails unless
+/// the pairs are for `1, 2, 4, ...` levels, as bit allocation assumes one
+/// error per whole bit starting at zero bits. Other level counts can be
+/// allocated with [`allocate_levels`].
+pub fn whole_bits(errors: &[(u64, f64)]) -> Result<Vec<f64>> {
+    errors
+        .iter()
+        .enumerate()
+        .map(|(bits, &(levels, error))| {
+            ensure!(
+                bits < 64 && levels == 1 << bits,
+                "expected {} levels for {} bits, got {}, use allocate_levels for other level counts",
+                1u128 << bits,
+                bits,
+                levels
+            );
+            Ok(error)
+        })
+        .collect()
+}
+
+/// An error for every number of bits, starting at zero bits.
+pub trait ErrorCurve {
+    /// Number of bit counts with a known error.
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// The error with `bits` bits, larger bit counts get the error of the
+    /// last known one.
+    fn error(&self, bits: usize) -> f64;
+    /// How `bits` bits are split up between the merged channels.
+    fn allocation(&self, bits: usize) -> &[usize];
+}
+
+impl<'a, const N: usize> ErrorCurve for ErrorFunction<'a, N> {
+    fn len(&self) -> usize {
+        N
+    }
+    fn error(&self, bits: usize) -> f64 {
+        self[bits]
+    }
+    fn allocation(&self, bits: usize) -> &[usize] {
+        &self.bits[bits.min(N - 1)]
+    }
+}
+
+/// [`ErrorFunction`] with a length chosen at runtime, e.g. from the length
+/// of a [`Sweep`](crate::sweep::Sweep) or a configured container size.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DynamicErrorFunction {
+    index: usize,
+    function: Vec<f64>,
+    pub bits: Vec<Vec<usize>>,
+}
+
+impl DynamicErrorFunction {
+    pub fn new(function: Vec<f64>) -> Self {
+        assert!(
+            !function.is_empty(),
+            "error functions need at least one value"
+        );
+        Self {
+            index: 0,
+            bits: (0..function.len()).map(|x| vec![x]).collect(),
+            function,
+        }
+    }
+
+    /// Error function of the `(levels, error)` pairs of a
+    /// [`Sweep`](crate::sweep::Sweep) over whole bit depths starting at zero
+    /// bits, see [`whole_bits`].
+    pub fn from_sweep(errors: &[(u64, f64)]) -> Result<Self> {
+        ensure!(!errors.is_empty(), "the sweep has no errors");
+        Ok(Self::new(whole_bits(errors)?))
+    }
+
+    /// An error function of `len` values which are filled by
+    /// [`DynamicErrorFunction::push`].
+    pub fn empty(len: usize) -> Self {
+        Self::new(vec![-1.0; len])
+    }
+
+    /// Appends the best split of the next bit count between `first` and
+    /// `second`, see [`ErrorFunction::push`].
+    pub fn push(&mut self, first: &impl ErrorCurve, second: &impl ErrorCurve) {
+        self.push_with(first, second, |a, b| a + b)
+    }
+
+    pub fn push_with(
+        &mut self,
+        first: &impl ErrorCurve,
+        second: &impl ErrorCurve,
+        combine: impl Fn(f64, f64) -> f64,
+    ) {
+        let index = self.index;
+        let (first_bits, min) = (0..=index)
+            .map(|i| (i, combine(first.error(i), second.error(index - i))))
+            .fold((index, f64::MAX), |best, candidate| {
+                if candidate.1 < best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            });
+        let slot = index.min(self.function.len() - 1);
+        self.function[slot] = min;
+        let mut bits = first.allocation(first_bits).to_vec();
+        bits.push(index - first_bits);
+        self.bits[slot] = bits;
+        self.index += 1;
+    }
+}
+
+impl Deref for DynamicErrorFunction {
+    type Target = [f64];
+
+    fn deref(&self) -> &Self::Target {
+        &self.function
+    }
+}
+
+impl Index<usize> for DynamicErrorFunction {
+    type Output = f64;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.function[index.min(self.function.len() - 1)]
+    }
+}
+
+impl ErrorCurve for DynamicErrorFunction {
+    fn len(&self) -> usize {
+        self.function.len()
+    }
+    fn error(&self, bits: usize) -> f64 {
+        self[bits]
+    }
+    fn allocation(&self, bits: usize) -> &[usize] {
+        &self.bits[bits.min(self.function.len() - 1)]
+    }
+}
+
+impl<'a, const N: usize> From<&ErrorFunction<'a, N>> for DynamicErrorFunction {
+    fn from(function: &ErrorFunction<'a, N>) -> Self {
+        Self {
+            index: function.index,
+            function: function.to_vec(),
+            bits: function.bits.to_vec(),
+        }
+    }
+}
+
+/// [`merge_error_functions`] for error functions with a runtime length,
+/// the result has `len` values.
+pub fn merge_dynamic_error_functions(
+    first: &impl ErrorCurve,
+    second: &impl ErrorCurve,
+    len: usize,
+) -> DynamicErrorFunction {
+    let mut combined = DynamicErrorFunction::empty(len);
+    for _ in 0..len {
+        combined.push(first, second);
+    }
+    combined
+}
+
 pub fn merge_error_functions<'a, const N: usize, const M: usize, const O: usize>(
     first: &ErrorFunction<'a, N>,
     second: &ErrorFunction<'a, M>,
@@ -100,3 +303,716 @@ pub fn merge_error_functions<'a, const N: usize, const M: usize, const O: usize>
     }
     combined
 }
+
+/// Merges two error functions whose values are costs of `metric`, see
+/// [`ErrorMetric::cost`](crate::metric::ErrorMetric::cost).
+pub fn merge_error_functions_with<'a, const N: usize, const M: usize, const O: usize>(
+    first: &ErrorFunction<'a, N>,
+    second: &ErrorFunction<'a, M>,
+    metric: crate::metric::ErrorMetric,
+) -> ErrorFunction<'a, O> {
+    let mut combined = ErrorFunction::empty();
+    for _ in 0..O {
+        combined.push_with(first, second, |a, b| metric.combine(a, b));
+    }
+    combined
+}
+
+/// Bits per channel chosen by [`allocate_bits`] for one container size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitAllocation {
+    pub bits: Vec<usize>,
+    /// The minimized error, with every channel scaled by its weight.
+    pub error: f64,
+    /// The error of the same allocation with all weights set to one.
+    pub unweighted_error: f64,
+}
+
+/// Allocates the bits of every container size from 0 to `container_bits`
+/// to any number of channels, minimizing the sum of their errors.
+///
+/// The result is indexed by the container size. Each channel gets fewer bits
+/// than the length of its curve and containers may be left partially unused.
+pub fn allocate_bits<C: ErrorCurve>(channels: &[C], container_bits: usize) -> Vec<BitAllocation> {
+    allocate_bits_with(channels, container_bits, |a, b| a + b)
+}
+
+/// Like [`allocate_bits`] but combines the errors of the channels with
+/// `combine`, e.g. [`ErrorMetric::combine`](crate::metric::ErrorMetric::combine).
+pub fn allocate_bits_with<C: ErrorCurve>(
+    channels: &[C],
+    container_bits: usize,
+    combine: impl Fn(f64, f64) -> f64,
+) -> Vec<BitAllocation> {
+    allocate_bits_weighted_with(channels, &vec![1.; channels.len()], container_bits, combine)
+        .expect("one weight per channel")
+}
+
+/// Like [`allocate_bits`] but scales the errors of every channel by its
+/// weight, e.g. to count green twice for Bayer data or to weight alpha low.
+/// Fails if the weights do not match the channels.
+pub fn allocate_bits_weighted<C: ErrorCurve>(
+    channels: &[C],
+    weights: &[f64],
+    container_bits: usize,
+) -> Result<Vec<BitAllocation>> {
+    allocate_bits_weighted_with(channels, weights, container_bits, |a, b| a + b)
+}
+
+pub fn allocate_bits_weighted_with<C: ErrorCurve>(
+    channels: &[C],
+    weights: &[f64],
+    container_bits: usize,
+    combine: impl Fn(f64, f64) -> f64,
+) -> Result<Vec<BitAllocation>> {
+    let unconstrained = Constraints::default();
+    Ok(
+        allocate_bits_constrained(channels, weights, &unconstrained, container_bits, combine)?
+            .into_iter()
+            .map(|allocation| allocation.expect("zero bits are always possible"))
+            .collect(),
+    )
+}
+
+/// Valid bit counts of a single channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelConstraint {
+    pub min: usize,
+    /// Largest bit count, fewer bits than the length of the error curve if
+    /// unset. Allocation fails if it is not below the length of the curve.
+    pub max: Option<usize>,
+    /// The bit count has to be a multiple of this.
+    pub multiple_of: usize,
+}
+
+impl Default for ChannelConstraint {
+    fn default() -> Self {
+        Self {
+            min: 0,
+            max: None,
+            multiple_of: 1,
+        }
+    }
+}
+
+impl ChannelConstraint {
+    pub fn between(min: usize, max: usize) -> Self {
+        Self {
+            min,
+            max: Some(max),
+            ..Self::default()
+        }
+    }
+    /// Exactly `bits` bits, e.g. for a 1-bit alpha or flag.
+    pub fn fixed(bits: usize) -> Self {
+        Self::between(bits, bits)
+    }
+    /// A channel occupying whole bytes.
+    pub fn byte_aligned() -> Self {
+        Self::default().with_multiple_of(8)
+    }
+
+    pub fn with_min(mut self, min: usize) -> Self {
+        self.min = min;
+        self
+    }
+    pub fn with_max(mut self, max: usize) -> Self {
+        self.max = Some(max);
+        self
+    }
+    pub fn with_multiple_of(mut self, multiple_of: usize) -> Self {
+        assert!(multiple_of > 0, "bit counts can not be a multiple of zero");
+        self.multiple_of = multiple_of;
+        self
+    }
+
+    /// The valid bit counts for an error curve of length `len`.
+    fn allowed(&self, len: usize) -> impl Iterator<Item = usize> + '_ {
+        let max = self.max.unwrap_or(len.saturating_sub(1));
+        (self.min..=max).filter(|bits| bits % self.multiple_of == 0)
+    }
+}
+
+/// Rules an allocation has to follow, e.g. the layout of a pixel format.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Constraints {
+    channels: Vec<ChannelConstraint>,
+    equal: Vec<(usize, usize)>,
+}
+
+impl Constraints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Constrains the bits of `channel`. Allocation fails if there is no
+    /// such channel.
+    pub fn with_channel(mut self, channel: usize, constraint: ChannelConstraint) -> Self {
+        if self.channels.len() <= channel {
+            self.channels
+                .resize(channel + 1, ChannelConstraint::default());
+        }
+        self.channels[channel] = constraint;
+        self
+    }
+
+    /// Requires both channels to get the same number of bits. Allocation
+    /// fails if there is no such channel.
+    pub fn with_equal(mut self, first: usize, second: usize) -> Self {
+        self.equal.push((first, second));
+        self
+    }
+
+    pub fn channel(&self, channel: usize) -> ChannelConstraint {
+        self.channels.get(channel).copied().unwrap_or_default()
+    }
+
+    /// Checks that the constraints refer to existing channels and bit counts
+    /// of the error curves with lengths `lens`.
+    fn check(&self, lens: &[usize]) -> Result<()> {
+        let channels = lens.len();
+        let referenced = self
+            .equal
+            .iter()
+            .flat_map(|&(first, second)| [first, second])
+            .chain(self.channels.len().checked_sub(1));
+        for channel in referenced {
+            ensure!(
+                channel < channels,
+                "constraint on channel {} of {} channels",
+                channel,
+                channels
+            );
+        }
+        for (channel, (constraint, &len)) in self.channels.iter().zip(lens).enumerate() {
+            if let Some(max) = constraint.max {
+                ensure!(
+                    max < len,
+                    "channel {} allows up to {} bits but its error curve ends at {} bits",
+                    channel,
+                    max,
+                    len.saturating_sub(1)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Groups of channels which get the same number of bits.
+    fn groups(&self, channels: usize) -> Vec<Vec<usize>> {
+        let mut group: Vec<usize> = (0..channels).collect();
+        fn root(group: &mut [usize], mut channel: usize) -> usize {
+            while group[channel] != channel {
+                group[channel] = group[group[channel]];
+                channel = group[channel];
+            }
+            channel
+        }
+        for &(first, second) in &self.equal {
+            let (first, second) = (root(&mut group, first), root(&mut group, second));
+            group[first] = second;
+        }
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut index = vec![usize::MAX; channels];
+        for channel in 0..channels {
+            let root = root(&mut group, channel);
+            if index[root] == usize::MAX {
+                index[root] = groups.len();
+                groups.push(Vec::new());
+            }
+            groups[index[root]].push(channel);
+        }
+        groups
+    }
+}
+
+/// Like [`allocate_bits_weighted_with`] but only considers allocations
+/// which satisfy `constraints`. The result is `None` for container sizes
+/// without a valid allocation. Fails if the weights or the constraints do
+/// not match the channels.
+pub fn allocate_bits_constrained<C: ErrorCurve>(
+    channels: &[C],
+    weights: &[f64],
+    constraints: &Constraints,
+    container_bits: usize,
+    combine: impl Fn(f64, f64) -> f64,
+) -> Result<Vec<Option<BitAllocation>>> {
+    ensure!(
+        channels.len() == weights.len(),
+        "{} weights for {} channels",
+        weights.len(),
+        channels.len()
+    );
+    let lens: Vec<_> = channels.iter().map(ErrorCurve::len).collect();
+    constraints.check(&lens)?;
+    if channels.is_empty() {
+        let empty = BitAllocation {
+            bits: Vec::new(),
+            error: 0.,
+            unweighted_error: 0.,
+        };
+        return Ok(vec![Some(empty); container_bits + 1]);
+    }
+    let groups = constraints.groups(channels.len());
+    // best[total] is the best (weighted error, bits per group) using exactly
+    // `total` bits
+    let mut best: Vec<Option<(Option<f64>, Vec<usize>)>> = vec![None; container_bits + 1];
+    best[0] = Some((None, Vec::new()));
+    for group in &groups {
+        // bit counts valid for every channel of the group
+        let mut allowed: Vec<usize> = constraints
+            .channel(group[0])
+            .allowed(channels[group[0]].len())
+            .collect();
+        for &channel in &group[1..] {
+            let valid: Vec<usize> = constraints
+                .channel(channel)
+                .allowed(channels[channel].len())
+                .collect();
+            allowed.retain(|bits| valid.contains(bits));
+        }
+        let mut next: Vec<Option<(Option<f64>, Vec<usize>)>> = vec![None; container_bits + 1];
+        for (used, previous) in best.iter().enumerate() {
+            let Some((previous_error, previous_bits)) = previous else {
+                continue;
+            };
+            for &bits in &allowed {
+                let total = used + bits * group.len();
+                if total > container_bits {
+                    continue;
+                }
+                let error = group
+                    .iter()
+                    .map(|&channel| weights[channel] * channels[channel].error(bits))
+                    .fold(*previous_error, |error, channel_error| {
+                        Some(error.map_or(channel_error, |e| combine(e, channel_error)))
+                    });
+                let slot = &mut next[total];
+                if slot.as_ref().is_none_or(|s| error < s.0) {
+                    let mut allocation = previous_bits.clone();
+                    allocation.push(bits);
+                    *slot = Some((error, allocation));
+                }
+            }
+        }
+        best = next;
+    }
+    // containers do not have to be filled completely
+    let mut result: Vec<Option<BitAllocation>> = Vec::with_capacity(container_bits + 1);
+    for allocation in best {
+        let previous = result.last().cloned().flatten();
+        let allocation = match (allocation, previous) {
+            (Some((error, _)), Some(p)) if Some(p.error) <= error => Some(p),
+            (Some((error, group_bits)), _) => {
+                let mut bits = vec![0; channels.len()];
+                for (group, &group_bits) in groups.iter().zip(&group_bits) {
+                    for &channel in group {
+                        bits[channel] = group_bits;
+                    }
+                }
+                let unweighted_error = channels
+                    .iter()
+                    .zip(&bits)
+                    .map(|(channel, &bits)| channel.error(bits))
+                    .reduce(&combine)
+                    .unwrap_or(0.);
+                Some(BitAllocation {
+                    bits,
+                    error: error.unwrap_or(0.),
+                    unweighted_error,
+                })
+            }
+            (None, previous) => previous,
+        };
+        result.push(allocation);
+    }
+    Ok(result)
+}
+
+/// What the allocator minimizes across channels.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Objective {
+    /// Total error of all channels.
+    Sum,
+    /// Total error with every channel scaled by its weight.
+    WeightedSum(Vec<f64>),
+    /// Error of the worst channel.
+    Max,
+    /// `(sum |error|^p)^(1/p)`, between [`Objective::Sum`] for `p = 1` and
+    /// [`Objective::Max`] for large `p`.
+    Lp(f64),
+}
+
+/// An error curve with every error raised to the power `p`.
+struct Powered<'a, C> {
+    curve: &'a C,
+    p: f64,
+}
+
+impl<C: ErrorCurve> ErrorCurve for Powered<'_, C> {
+    fn len(&self) -> usize {
+        self.curve.len()
+    }
+    fn error(&self, bits: usize) -> f64 {
+        self.curve.error(bits).abs().powf(self.p)
+    }
+    fn allocation(&self, bits: usize) -> &[usize] {
+        self.curve.allocation(bits)
+    }
+}
+
+/// Allocates bits minimizing `objective` exactly. `error` of every
+/// allocation is the value of the objective, `unweighted_error` the same
+/// objective without weights.
+///
+/// Sums are solved by the dynamic program over summed errors, the maximum by
+/// the same program over the maximum, which is exact since the maximum never
+/// decreases when adding channels. The Lp-norm minimizes the sum of the
+/// powered errors, which has the same optimum.
+///
+/// Fails for invalid constraints, see [`allocate_bits_constrained`], and
+/// for an Lp-norm with `p < 1`.
+pub fn allocate_bits_for<C: ErrorCurve>(
+    channels: &[C],
+    objective: &Objective,
+    constraints: &Constraints,
+    container_bits: usize,
+) -> Result<Vec<Option<BitAllocation>>> {
+    let ones = vec![1.; channels.len()];
+    let sum = |a: f64, b: f64| a + b;
+    match objective {
+        Objective::Sum => {
+            allocate_bits_constrained(channels, &ones, constraints, container_bits, sum)
+        }
+        Objective::WeightedSum(weights) => {
+            allocate_bits_constrained(channels, weights, constraints, container_bits, sum)
+        }
+        Objective::Max => {
+            allocate_bits_constrained(channels, &ones, constraints, container_bits, f64::max)
+        }
+        Objective::Lp(p) => {
+            ensure!(*p >= 1., "the Lp-norm needs p >= 1, got {}", p);
+            let powered: Vec<_> = channels
+                .iter()
+                .map(|curve| Powered { curve, p: *p })
+                .collect();
+            let mut allocations =
+                allocate_bits_constrained(&powered, &ones, constraints, container_bits, sum)?;
+            for allocation in allocations.iter_mut().flatten() {
+                allocation.error = allocation.error.powf(1. / p);
+                allocation.unweighted_error = allocation.error;
+            }
+            Ok(allocations)
+        }
+    }
+}
+
+/// The bits of `channel` for every container size, zero where no valid
+/// allocation exists, e.g. for [`crate::plot::plot_errors_with_bits`].
+pub fn channel_bits(allocations: &[Option<BitAllocation>], channel: usize) -> Vec<usize> {
+    allocations
+        .iter()
+        .map(|allocation| allocation.as_ref().map_or(0, |a| a.bits[channel]))
+        .collect()
+}
+
+/// Errors of one channel for arbitrary level counts, e.g. from a
+/// [`Sweep::from_levels`](crate::sweep::Sweep::from_levels), see
+/// [`LevelErrors::from_sweep`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelErrors {
+    pub levels: Vec<u64>,
+    pub errors: Vec<f64>,
+}
+
+impl LevelErrors {
+    pub fn new(levels: Vec<u64>, errors: Vec<f64>) -> Self {
+        assert_eq!(levels.len(), errors.len(), "one error per level count");
+        Self { levels, errors }
+    }
+
+    /// The `(levels, error)` pairs of a [`Sweep`](crate::sweep::Sweep), with
+    /// any level counts.
+    pub fn from_sweep(errors: &[(u64, f64)]) -> Self {
+        let (levels, errors) = errors.iter().copied().unzip();
+        Self { levels, errors }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, f64)> + '_ {
+        self.levels.iter().copied().zip(self.errors.iter().copied())
+    }
+}
+
+/// Level counts chosen for every channel by [`allocate_levels`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelAllocation {
+    pub levels: Vec<u64>,
+    pub error: f64,
+}
+
+impl LevelAllocation {
+    pub fn radix(&self) -> MixedRadix {
+        MixedRadix::new(self.levels.clone())
+    }
+}
+
+/// Chooses one level count per channel so that the product of all level
+/// counts fits into `capacity` codes and the `combine`d error is minimal.
+///
+/// Only `floor(capacity / d)` matters for the remaining channels after
+/// choosing level counts with product `d`, so the dynamic program has
+/// `O(sqrt(capacity))` states per channel. Returns `None` if no combination
+/// fits. Allocations with the same error are ordered by the capacity they
+/// leave, so ties are broken deterministically in favour of the allocation
+/// with the least unused capacity.
+pub fn allocate_levels(
+    channels: &[LevelErrors],
+    capacity: u64,
+    combine: impl Fn(f64, f64) -> f64,
+) -> Option<LevelAllocation> {
+    // remaining capacity -> (error so far, levels so far)
+    let mut states: BTreeMap<u64, (Option<f64>, Vec<u64>)> = BTreeMap::new();
+    states.insert(capacity, (None, Vec::new()));
+    for channel in channels {
+        let mut next: BTreeMap<u64, (Option<f64>, Vec<u64>)> = BTreeMap::new();
+        for (&remaining, (error, levels)) in &states {
+            for (count, channel_error) in channel.iter() {
+                if count == 0 || count > remaining {
+                    continue;
+                }
+                let error = Some(error.map_or(channel_error, |e| combine(e, channel_error)));
+                let entry = next.entry(remaining / count).or_insert((None, Vec::new()));
+                if entry.0.is_none() || error < entry.0 {
+                    let mut levels = levels.clone();
+                    levels.push(count);
+                    *entry = (error, levels);
+                }
+            }
+        }
+        states = next;
+    }
+    states
+        .into_values()
+        .filter_map(|(error, levels)| Some((error?, levels)))
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(error, levels)| LevelAllocation { levels, error })
+}
+
+/// Packs one code per channel into a single integer, using the level count
+/// of each channel as its radix. The first channel is the least significant
+/// digit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MixedRadix {
+    radices: Vec<u64>,
+}
+
+impl MixedRadix {
+    pub fn new(radices: Vec<u64>) -> Self {
+        assert!(
+            radices.iter().all(|&r| r > 0),
+            "radices have to be positive"
+        );
+        assert!(
+            radices
+                .iter()
+                .try_fold(1u64, |product, &r| product.checked_mul(r))
+                .is_some(),
+            "mixed radix code does not fit into 64 bits"
+        );
+        Self { radices }
+    }
+
+    pub fn radices(&self) -> &[u64] {
+        &self.radices
+    }
+
+    /// Number of distinct packed codes.
+    pub fn capacity(&self) -> u64 {
+        self.radices.iter().product()
+    }
+
+    /// Information content of a packed code, `log2(capacity)`.
+    pub fn bits(&self) -> f64 {
+        (self.capacity() as f64).log2()
+    }
+
+    /// Fails unless there is one code per channel and every code is below
+    /// the radix of its channel.
+    pub fn encode(&self, codes: &[u64]) -> Result<u64> {
+        ensure!(
+            codes.len() == self.radices.len(),
+            "{} codes for {} channels",
+            codes.len(),
+            self.radices.len()
+        );
+        for (channel, (&code, &radix)) in codes.iter().zip(&self.radices).enumerate() {
+            ensure!(
+                code < radix,
+                "code {} of channel {} is out of range for {} levels",
+                code,
+                channel,
+                radix
+            );
+        }
+        Ok(codes
+            .iter()
+            .zip(&self.radices)
+            .rev()
+            .fold(0, |packed, (&code, &radix)| packed * radix + code))
+    }
+
+    pub fn decode(&self, mut packed: u64) -> Vec<u64> {
+        self.radices
+            .iter()
+            .map(|&radix| {
+                let code = packed % radix;
+                packed /= radix;
+                code
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bit_allocation() {
+        let red = ErrorFunction::<4>::new(&[8., 4., 2., 1.]);
+        let green = ErrorFunction::<4>::new(&[16., 4., 1., 0.5]);
+        let alpha = ErrorFunction::<4>::new(&[0.1, 0.1, 0.1, 0.1]);
+        let allocations = allocate_bits(&[red, green, alpha], 8);
+        assert_eq!(allocations.len(), 9);
+        assert_eq!(allocations[0].bits, vec![0, 0, 0]);
+        assert_eq!(allocations[2].bits, vec![1, 1, 0]);
+        assert_eq!(allocations[4].bits, vec![2, 2, 0]);
+        assert_eq!(allocations[4].error, 3.1);
+        assert_eq!(allocations[8].bits, vec![3, 3, 0]);
+    }
+
+    #[test]
+    fn weighted_allocation() {
+        let curve = || DynamicErrorFunction::new(vec![8., 4., 2., 1.]);
+        let channels = [curve(), curve(), curve()];
+        let allocations = allocate_bits_weighted(&channels, &[1., 3., 0.1], 4).unwrap();
+        assert_eq!(allocations[4].bits, vec![1, 3, 0]);
+        assert_eq!(allocations[4].error, 4. + 3. + 0.8);
+        assert_eq!(allocations[4].unweighted_error, 4. + 1. + 8.);
+        assert_eq!(
+            allocate_bits(&channels, 4)[4].unweighted_error,
+            4. + 4. + 2.
+        );
+        assert!(allocate_bits_weighted(&channels, &[1., 3.], 4).is_err());
+    }
+
+    #[test]
+    fn constrained_allocation() {
+        let curve = || DynamicErrorFunction::new((0..12).map(|b| 0.5f64.powi(b)).collect());
+        let channels = [curve(), curve(), curve(), curve()];
+        let constraints = Constraints::new()
+            .with_channel(0, ChannelConstraint::fixed(1))
+            .with_channel(1, ChannelConstraint::byte_aligned().with_min(8))
+            .with_equal(2, 3);
+        let allocate = |constraints: &Constraints| {
+            allocate_bits_constrained(&channels, &[1.; 4], constraints, 24, |a, b| a + b)
+        };
+        let allocations = allocate(&constraints).unwrap();
+        assert!(allocations[..9].iter().all(Option::is_none));
+        assert_eq!(allocations[9].as_ref().unwrap().bits, vec![1, 8, 0, 0]);
+        assert_eq!(allocations[10].as_ref().unwrap().bits, vec![1, 8, 0, 0]);
+        assert_eq!(allocations[11].as_ref().unwrap().bits, vec![1, 8, 1, 1]);
+        assert_eq!(allocations[24].as_ref().unwrap().bits, vec![1, 8, 7, 7]);
+
+        let fixed = ChannelConstraint::fixed(1);
+        assert!(allocate(&Constraints::new().with_channel(4, fixed)).is_err());
+        assert!(allocate(&Constraints::new().with_equal(0, 4)).is_err());
+        let max = ChannelConstraint::between(0, 12);
+        assert!(allocate(&Constraints::new().with_channel(0, max)).is_err());
+        assert!(
+            allocate(&Constraints::new().with_channel(0, ChannelConstraint::fixed(11))).is_ok()
+        );
+        assert!(
+            allocate_bits_constrained(&channels, &[1.; 3], &Constraints::new(), 24, |a, b| a + b)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn objectives() {
+        let red = DynamicErrorFunction::new(vec![10., 5., 2., 1.5, 1.4]);
+        let blue = DynamicErrorFunction::new(vec![10., 3., 2.6, 2.5, 2.4]);
+        let channels = [red, blue];
+        let allocate = |objective| {
+            allocate_bits_for(&channels, &objective, &Constraints::new(), 4).unwrap()[4]
+                .clone()
+                .unwrap()
+        };
+        let sum = allocate(Objective::Sum);
+        assert_eq!((sum.bits, sum.error), (vec![3, 1], 4.5));
+        let max = allocate(Objective::Max);
+        assert_eq!((max.bits, max.error), (vec![2, 2], 2.6));
+        let weighted = allocate(Objective::WeightedSum(vec![1., 8.]));
+        assert_eq!(weighted.bits, vec![2, 2]);
+        assert!((weighted.unweighted_error - 4.6).abs() < 1e-12);
+        let l2 = allocate(Objective::Lp(2.));
+        assert_eq!(l2.bits, vec![2, 2]);
+        assert!((l2.error - 10.76f64.sqrt()).abs() < 1e-12);
+        let constraints = Constraints::new();
+        assert!(allocate_bits_for(&channels, &Objective::Lp(0.5), &constraints, 4).is_err());
+    }
+
+    #[test]
+    fn dynamic_matches_const() {
+        let first = [1.0, 0.8, 0.6, 0.4, 0.4, 0.1, 0.1, 0.1];
+        let second = [1.0, 0.9, 0.8, 0.8, 0.3, 0.2, 0.1, 0.0];
+        let merged: ErrorFunction<12> = merge_error_functions(
+            &ErrorFunction::<8>::new(&first),
+            &ErrorFunction::<8>::new(&second),
+        );
+        let dynamic = merge_dynamic_error_functions(
+            &DynamicErrorFunction::new(first.to_vec()),
+            &DynamicErrorFunction::new(second.to_vec()),
+            12,
+        );
+        assert_eq!(dynamic, DynamicErrorFunction::from(&merged));
+        assert_eq!(
+            allocate_bits(std::slice::from_ref(&dynamic), 20),
+            allocate_bits(&[merged], 20)
+        );
+    }
+
+    #[test]
+    fn mixed_radix_allocation() {
+        // the error is inversely proportional to the level count
+        let channel = |scale: f64| {
+            let levels: Vec<u64> = (2..=16).collect();
+            let errors = levels.iter().map(|&l| scale / l as f64).collect();
+            LevelErrors::new(levels, errors)
+        };
+        let channels = [channel(1.), channel(2.), channel(1.)];
+        let allocation = allocate_levels(&channels, 256, |a, b| a + b).unwrap();
+        // the green channel gets more levels since its errors weigh double
+        assert_eq!(allocation.levels, vec![5, 10, 5]);
+        assert!(allocation.radix().capacity() <= 256);
+        assert!(allocate_levels(&channels, 7, |a, b| a + b).is_none());
+
+        let radix = allocation.radix();
+        for packed in 0..radix.capacity() {
+            assert_eq!(radix.encode(&radix.decode(packed)).unwrap(), packed);
+        }
+        assert_eq!(radix.encode(&[4, 0, 0]).unwrap(), 4);
+        assert!(radix.encode(&[5, 0, 0]).is_err());
+        assert!(radix.encode(&[0, 0]).is_err());
+
+        // equal errors for every level count
+        let flat = LevelErrors::from_sweep(&[(2, 1.), (3, 1.), (4, 1.)]);
+        assert_eq!(flat.levels, vec![2, 3, 4]);
+        let channels = [flat.clone(), flat.clone(), flat];
+        let first = allocate_levels(&channels, 30, |a, b| a + b).unwrap();
+        for _ in 0..10 {
+            assert_eq!(allocate_levels(&channels, 30, |a, b| a + b).unwrap(), first);
+        }
+        assert_eq!(first.levels, vec![4, 3, 2]);
+    }
+}
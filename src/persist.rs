@@ -0,0 +1,218 @@
+//! Versioned on-disk format for fitted models.
+//!
+//! Models are stored as a small line based text file:
+//!
+//! ```text
+//! autoquant-model 1
+//! kind log
+//! levels 40
+//! parameters 0.01 0.3 0.5 4
+//! normalization 0 16383
+//! error 1.25
+//! samples 4096
+//! ```
+//!
+//! Unknown keys are ignored so newer writers stay readable by older readers
+//! of the same version.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::{FitFn, Normalization};
+
+pub const MAGIC: &str = "autoquant-model";
+pub const VERSION: u32 = 1;
+
+/// Information about how a model was fitted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FitMetadata {
+    /// Error of the fit on its training distribution.
+    pub error: Option<f64>,
+    /// Number of distribution points the model was fitted on.
+    pub samples: usize,
+}
+
+impl FitMetadata {
+    pub fn from_report(report: &crate::models::FitReport, samples: usize) -> Self {
+        Self {
+            error: Some(report.best_cost),
+            samples,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelFile {
+    pub kind: String,
+    pub parameters: Vec<f64>,
+    pub levels: u64,
+    pub normalization: Normalization,
+    pub metadata: FitMetadata,
+}
+
+impl ModelFile {
+    pub fn from_fit(
+        fit: &dyn FitFn,
+        levels: u64,
+        normalization: Normalization,
+        metadata: FitMetadata,
+    ) -> Self {
+        Self {
+            kind: fit.name().to_string(),
+            parameters: fit.parameters().to_vec(),
+            levels,
+            normalization,
+            metadata,
+        }
+    }
+
+    /// Reconstructs the fitted function described by this file.
+    pub fn to_fit_fn(&self) -> Result<Box<dyn FitFn>> {
+        crate::registry::from_parameters(&self.kind, self.parameters.clone())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        Self::read(BufReader::new(file)).with_context(|| format!("reading {}", path.display()))
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "{} {}", MAGIC, VERSION)?;
+        writeln!(writer, "kind {}", self.kind)?;
+        writeln!(writer, "levels {}", self.levels)?;
+        write!(writer, "parameters")?;
+        for parameter in &self.parameters {
+            write!(writer, " {}", parameter)?;
+        }
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "normalization {} {}",
+            self.normalization.offset, self.normalization.scale
+        )?;
+        if let Some(error) = self.metadata.error {
+            writeln!(writer, "error {}", error)?;
+        }
+        writeln!(writer, "samples {}", self.metadata.samples)?;
+        Ok(())
+    }
+
+    pub fn read(reader: impl BufRead) -> Result<Self> {
+        let mut lines = reader.lines();
+        let header = lines.next().context("empty model file")??;
+        match header.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [MAGIC, version] => {
+                let version: u32 = version.parse().context("invalid version")?;
+                ensure!(
+                    version == VERSION,
+                    "unsupported model version {}, expected {}",
+                    version,
+                    VERSION
+                );
+            }
+            _ => bail!("not an autoquant model file"),
+        }
+
+        let mut kind = None;
+        let mut parameters = None;
+        let mut levels = None;
+        let mut normalization = Normalization::default();
+        let mut metadata = FitMetadata::default();
+        for line in lines {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            let Some(key) = fields.next() else {
+                continue;
+            };
+            let values: Vec<&str> = fields.collect();
+            match key {
+                "kind" => kind = Some(values.join(" ")),
+                "levels" => levels = Some(parse_one(key, &values)?),
+                "parameters" => parameters = Some(parse_floats(key, &values)?),
+                "normalization" => match parse_floats(key, &values)?.as_slice() {
+                    &[offset, scale] => normalization = Normalization::new(offset, scale),
+                    _ => bail!("normalization expects an offset and a scale"),
+                },
+                "error" => metadata.error = Some(parse_one(key, &values)?),
+                "samples" => metadata.samples = parse_one(key, &values)?,
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            kind: kind.context("missing model kind")?,
+            parameters: parameters.context("missing model parameters")?,
+            levels: levels.context("missing level count")?,
+            normalization,
+            metadata,
+        })
+    }
+}
+
+fn parse_one<T: std::str::FromStr>(key: &str, values: &[&str]) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match values {
+        [value] => value.parse().with_context(|| format!("invalid {}", key)),
+        _ => bail!("{} expects exactly one value", key),
+    }
+}
+
+fn parse_floats(key: &str, values: &[&str]) -> Result<Vec<f64>> {
+    values
+        .iter()
+        .map(|value| value.parse().with_context(|| format!("invalid {}", key)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::OptimizedLog, CreateFitFn};
+
+    #[test]
+    fn round_trip() {
+        let model = <OptimizedLog as CreateFitFn>::new(vec![0.01, 0.3, 0.5, 4.]);
+        let file = ModelFile::from_fit(
+            &model,
+            40,
+            Normalization::new(0., 16383.),
+            FitMetadata {
+                error: Some(1.25),
+                samples: 4096,
+            },
+        );
+        let mut buffer = Vec::new();
+        file.write(&mut buffer).unwrap();
+        let loaded = ModelFile::read(buffer.as_slice()).unwrap();
+        assert_eq!(loaded, file);
+
+        let fit = loaded.to_fit_fn().unwrap();
+        assert_eq!(fit.function(0.5), model.function(0.5));
+    }
+
+    #[test]
+    fn reads_version_one_files() {
+        let file = "autoquant-model 1\nkind powf\nlevels 16\nparameters 0 1 0 1 0.5\n";
+        let fit = ModelFile::read(file.as_bytes())
+            .unwrap()
+            .to_fit_fn()
+            .unwrap();
+        assert_eq!(fit.name(), "powf");
+        assert_eq!(fit.function(0.25), 0.5);
+    }
+}
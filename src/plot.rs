@@ -1,4 +1,4 @@
-use plotters::{prelude::*, style::full_palette::PINK};
+use plotters::{coord::Shift, prelude::*, style::full_palette::PINK};
 
 use crate::FitFn;
 const OUT_FILE_NAME: &str = "histogram.svg";
@@ -117,8 +117,14 @@ pub fn plot_channels(data: &[&[(f64, f64)]]) -> Result<(), Box<dyn std::error::E
     Ok(())
 }
 
+/// Bit depth `log2(levels)` of a level count, the x axis of the error plots.
+fn bits(levels: u64) -> f64 {
+    (levels as f64).log2()
+}
+
+/// Draws the `(levels, error)` curves of a sweep over the bit depth.
 pub fn plot_errors(
-    data: &[Vec<f64>],
+    data: &[Vec<(u64, f64)>],
     names: &[String],
     color: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -127,11 +133,15 @@ pub fn plot_errors(
 
     root.fill(&WHITE)?;
     let minx = 0.;
-    let maxx = data[0].len() as f64;
+    let maxx = data
+        .iter()
+        .flatten()
+        .map(|&(levels, _)| bits(levels))
+        .fold(1., f64::max);
     let miny = 0.;
     let maxy = data
         .iter()
-        .map(|x| x.iter().cloned().fold(0., f64::max))
+        .map(|x| x.iter().map(|&(_, error)| error).fold(0., f64::max))
         .fold(0., f64::max);
 
     let caption = format!("Quantization error functions for the {} channel", color);
@@ -155,7 +165,7 @@ pub fn plot_errors(
         let color = Palette99::pick(i + 3);
         chart
             .draw_series(LineSeries::new(
-                data.iter().cloned().enumerate().map(|(x, y)| (x as f64, y)),
+                data.iter().map(|&(levels, error)| (bits(levels), error)),
                 &color,
             ))?
             .label(&names[i])
@@ -183,27 +193,84 @@ pub fn plot_errors_with_bits(
     let root = SVGBackend::new("out/combined_error.svg", (1200, 800)).into_drawing_area();
 
     root.fill(&WHITE)?;
+    draw_errors_with_bits(
+        &root,
+        "Error functions and bit allocation",
+        data,
+        bits,
+        names,
+    )?;
+
+    // To avoid the IO failure being ignored silently, we manually call the present function
+    root.present().expect("Unable to write result to file, please make sure 'plotters-doc-data' dir exists under current dir");
+    println!("Result has been saved to {}", OUT_FILE_NAME);
+
+    Ok(())
+}
+
+/// Draws [`plot_errors_with_bits`] once per allocation objective, stacked
+/// on top of each other for comparison.
+pub fn plot_allocations(
+    data: &[Vec<f64>],
+    allocations: &[(String, Vec<Vec<usize>>)],
+    names: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let name = "out/allocations.svg";
+    let height = 800 * allocations.len() as u32;
+    let root = SVGBackend::new(name, (1200, height)).into_drawing_area();
+
+    root.fill(&WHITE)?;
+    for (area, (objective, bits)) in root
+        .split_evenly((allocations.len(), 1))
+        .iter()
+        .zip(allocations)
+    {
+        let bits: Vec<&[usize]> = bits.iter().map(Vec::as_slice).collect();
+        let caption = format!("Bit allocation minimizing the {}", objective);
+        draw_errors_with_bits(area, &caption, data, &bits, names)?;
+    }
+
+    // To avoid the IO failure being ignored silently, we manually call the present function
+    root.present().expect("Unable to write result to file, please make sure 'plotters-doc-data' dir exists under current dir");
+    println!("Result has been saved to {}", name);
+
+    Ok(())
+}
+
+fn draw_errors_with_bits(
+    root: &DrawingArea<SVGBackend, Shift>,
+    caption: &str,
+    data: &[Vec<f64>],
+    bits: &[&[usize]],
+    names: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
     let maxx = data[0].len() as f64;
     let maxy = data
         .iter()
         .map(|x| x.iter().cloned().fold(0., f64::max))
         .fold(0., f64::max);
+    let containers = bits.first().map_or(0, |b| b.len()) as f32;
+    let max_bits = bits
+        .iter()
+        .flat_map(|b| b.iter())
+        .max()
+        .map_or(1, |&b| b as u32 + 1);
 
-    let mut chart = ChartBuilder::on(&root)
+    let mut chart = ChartBuilder::on(root)
         .margin(10)
-        .caption("Error functions and bit allocation", ("sans-serif", 40.0))
+        .caption(caption, ("sans-serif", 40.0))
         .set_label_area_size(LabelAreaPosition::Left, 60)
         .set_label_area_size(LabelAreaPosition::Right, 60)
         .set_label_area_size(LabelAreaPosition::Bottom, 40)
         .set_label_area_size(LabelAreaPosition::Top, 40)
         .build_cartesian_2d(0f64..maxx, 0f64..maxy)?
-        .set_secondary_coord(0f32..24f32, 0..16u32);
+        .set_secondary_coord(0f32..containers, 0..max_bits);
 
     /*let mut chart = ChartBuilder::on(&root)
             .x_label_area_size(35)
             .y_label_area_size(80)
             .margin(5)
-            .caption("Error functions and bit allocation", ("sans-serif", 40.0))
+            .caption(caption, ("sans-serif", 40.0))
             .build_cartesian_2d(0..(maxx as u32), 0f64..maxy)?
             .set_secondary_coord(0..32usize, 0..32usize);
     */
@@ -258,6 +325,74 @@ pub fn plot_errors_with_bits(
             .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
     }
 
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+
+    Ok(())
+}
+
+/// Like [`plot_errors`] but draws the mean of bootstrapped errors together
+/// with the shaded percentile band.
+#[cfg(feature = "fitting")]
+pub fn plot_error_bands(
+    data: &[crate::bootstrap::SweepBands],
+    names: &[String],
+    color: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let name = format!("out/error_bands_{}.svg", color);
+    let root = SVGBackend::new(name.as_str(), (800, 600)).into_drawing_area();
+
+    root.fill(&WHITE)?;
+    let maxx = data
+        .iter()
+        .flatten()
+        .map(|&(levels, _)| bits(levels))
+        .fold(1., f64::max);
+    let maxy = data
+        .iter()
+        .map(|x| x.iter().map(|(_, band)| band.upper).fold(0., f64::max))
+        .fold(0., f64::max);
+
+    let caption = format!("Bootstrapped error functions for the {} channel", color);
+    let mut chart = ChartBuilder::on(&root)
+        .x_label_area_size(35)
+        .y_label_area_size(80)
+        .margin(5)
+        .caption(caption.as_str(), ("sans-serif", 40.0))
+        .build_cartesian_2d(0f64..maxx, 0f64..maxy)?;
+
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .bold_line_style(WHITE.mix(0.3))
+        .y_desc("Relative Error")
+        .x_desc("Number of bits")
+        .axis_desc_style(("sans-serif", 15))
+        .draw()?;
+
+    for (i, bands) in data.iter().enumerate() {
+        let color = Palette99::pick(i + 3);
+        let upper = bands.iter().map(|(levels, b)| (bits(*levels), b.upper));
+        let lower = bands
+            .iter()
+            .rev()
+            .map(|(levels, b)| (bits(*levels), b.lower));
+        chart.draw_series(std::iter::once(Polygon::new(
+            upper.chain(lower).collect::<Vec<_>>(),
+            color.mix(0.2).filled(),
+        )))?;
+        chart
+            .draw_series(LineSeries::new(
+                bands.iter().map(|(levels, b)| (bits(*levels), b.mean)),
+                &color,
+            ))?
+            .label(&names[i])
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], &color));
+    }
+
     chart
         .configure_series_labels()
         .background_style(WHITE.mix(0.8))
@@ -266,7 +401,7 @@ pub fn plot_errors_with_bits(
 
     // To avoid the IO failure being ignored silently, we manually call the present function
     root.present().expect("Unable to write result to file, please make sure 'plotters-doc-data' dir exists under current dir");
-    println!("Result has been saved to {}", OUT_FILE_NAME);
+    println!("Result has been saved to {}", name);
 
     Ok(())
 }
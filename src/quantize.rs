@@ -0,0 +1,320 @@
+//! Rounding, dither and reconstruction modes of the quantizer.
+
+use crate::FitFn;
+
+/// How a mapped value in [0, 1] is turned into a code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Truncate towards zero, like a plain `as u64` cast.
+    #[default]
+    Floor,
+    Nearest,
+    /// Round up with a probability equal to the fractional part, so the
+    /// expected code equals the mapped value.
+    Stochastic,
+}
+
+/// Which value a code is decoded to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Reconstruction {
+    /// `inverse(k / samples)`, the lower edge of the bin in the encoded domain.
+    #[default]
+    LowerEdge,
+    /// `inverse((k + 0.5) / samples)`, the middle of the bin in the encoded
+    /// domain.
+    EncodedMidpoint,
+    /// The mean of `inverse(k / samples)` and `inverse((k + 1) / samples)`,
+    /// the middle of the bin in the input domain.
+    InputMidpoint,
+}
+
+/// Noise added to the scaled value before rounding, in units of one code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dither {
+    #[default]
+    None,
+    /// Uniform noise in [-0.5, 0.5).
+    Uniform,
+    /// Triangular noise in (-1, 1), the sum of two uniform samples.
+    Tpdf,
+    /// Deterministic R2 low-discrepancy sequence over the pixel grid, shifted
+    /// by `seed`. It spreads the offsets evenly like blue noise without
+    /// being spectrally shaped, and [`Quantizer::outcomes`] treats it as
+    /// uniform noise.
+    LowDiscrepancy { seed: u64 },
+    /// 8x8 Bayer matrix.
+    Ordered,
+}
+
+impl Dither {
+    /// The offset added at pixel `(x, y)`. `noise` draws uniform samples
+    /// from [0, 1) for the random variants.
+    pub fn offset(&self, (x, y): (u32, u32), noise: &mut dyn FnMut() -> f64) -> f64 {
+        match self {
+            Dither::None => 0.,
+            Dither::Uniform => noise() - 0.5,
+            Dither::Tpdf => noise() + noise() - 1.,
+            Dither::LowDiscrepancy { seed } => {
+                // plastic number, see http://extremelearning.com.au/unreasonable-effectiveness-of-quasirandom-sequences/
+                const G: f64 = 1.324_717_957_244_746;
+                let phase = (*seed as f64 * 0.618_033_988_749_895).fract();
+                (x as f64 / G + y as f64 / (G * G) + phase).fract() - 0.5
+            }
+            Dither::Ordered => (bayer(x, y) as f64 + 0.5) / 64. - 0.5,
+        }
+    }
+}
+
+/// Index of `(x, y)` in the 8x8 Bayer matrix.
+fn bayer(x: u32, y: u32) -> u32 {
+    (0..3)
+        .map(|bit| {
+            let (x, y) = ((x >> bit) & 1, (y >> bit) & 1);
+            (2 * (x ^ y) + y) << (2 * (2 - bit))
+        })
+        .sum()
+}
+
+/// `P(X < x)` for the sum `X` of `n` independent uniform samples from [0, 1).
+fn irwin_hall_cdf(n: u32, x: f64) -> f64 {
+    if x <= 0. {
+        return 0.;
+    }
+    if x >= n as f64 {
+        return 1.;
+    }
+    let factorial: f64 = (1..=n).map(|i| i as f64).product();
+    let mut binomial = 1.;
+    let mut sum = 0.;
+    for k in 0..=x.floor() as u32 {
+        let sign = if k % 2 == 0 { 1. } else { -1. };
+        sum += sign * binomial * (x - k as f64).powi(n as i32);
+        binomial *= (n - k) as f64 / (k + 1) as f64;
+    }
+    sum / factorial
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quantizer {
+    pub rounding: Rounding,
+    pub dither: Dither,
+    pub reconstruction: Reconstruction,
+}
+
+impl Quantizer {
+    pub fn new(rounding: Rounding, reconstruction: Reconstruction) -> Self {
+        Self {
+            rounding,
+            dither: Dither::None,
+            reconstruction,
+        }
+    }
+
+    pub fn with_dither(mut self, dither: Dither) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Encodes `value` at pixel `pixel` into one of the codes `0..samples`.
+    ///
+    /// `noise` draws uniform samples from [0, 1) for the random dithers and
+    /// [`Rounding::Stochastic`].
+    pub fn encode(
+        &self,
+        value: f64,
+        fit: &dyn FitFn,
+        samples: u64,
+        pixel: (u32, u32),
+        noise: &mut dyn FnMut() -> f64,
+    ) -> u64 {
+        let scaled = scaled(value, fit, samples) + self.dither.offset(pixel, noise);
+        let code = match self.rounding {
+            Rounding::Floor => scaled.floor(),
+            Rounding::Nearest => (scaled + 0.5).floor(),
+            Rounding::Stochastic => (scaled + noise()).floor(),
+        };
+        code.clamp(0., last_code(samples) as f64) as u64
+    }
+
+    /// The codes `value` can be encoded to together with their probability.
+    ///
+    /// Codes are `floor(scaled + offset)` where the offset is the sum of the
+    /// dither and the rounding offset. Deterministic dither patterns are
+    /// treated as uniformly distributed over the image.
+    pub fn outcomes(
+        &self,
+        value: f64,
+        fit: &dyn FitFn,
+        samples: u64,
+    ) -> impl Iterator<Item = (u64, f64)> {
+        // the offset is `constant + i / grid + X` for a uniformly chosen
+        // `i < grid` and the sum `X` of `uniforms` uniform samples
+        let (mut constant, grid, mut uniforms) = match self.dither {
+            Dither::None => (0., 1, 0),
+            Dither::Uniform | Dither::LowDiscrepancy { .. } => (-0.5, 1, 1),
+            Dither::Tpdf => (-1., 1, 2),
+            Dither::Ordered => (0.5 / 64. - 0.5, 64, 0),
+        };
+        match self.rounding {
+            Rounding::Floor => {}
+            Rounding::Nearest => constant += 0.5,
+            Rounding::Stochastic => uniforms += 1,
+        }
+        let start = scaled(value, fit, samples) + constant;
+        let first = start.floor() as i64;
+        let last = (start + (grid - 1) as f64 / grid as f64 + uniforms as f64).floor() as i64;
+        (first..=last).map(move |code| {
+            let probability = (0..grid)
+                .map(|i| {
+                    let lower = code as f64 - start - i as f64 / grid as f64;
+                    irwin_hall_cdf(uniforms, lower + 1.) - irwin_hall_cdf(uniforms, lower)
+                })
+                .sum::<f64>()
+                / grid as f64;
+            (code.clamp(0, last_code(samples) as i64) as u64, probability)
+        })
+    }
+
+    pub fn decode(&self, code: u64, fit: &dyn FitFn, samples: u64) -> f64 {
+        let inverse = |position: f64| {
+            let mut x = (position / samples as f64).min(1.);
+            if !x.is_finite() {
+                x = 0.;
+            }
+            x = fit.inverse(x);
+            if !x.is_finite() {
+                x = 0.;
+            }
+            x
+        };
+        let code = code as f64;
+        match self.reconstruction {
+            Reconstruction::LowerEdge => inverse(code),
+            Reconstruction::EncodedMidpoint => inverse(code + 0.5),
+            Reconstruction::InputMidpoint => (inverse(code) + inverse(code + 1.)) / 2.,
+        }
+    }
+}
+
+/// The highest of the `samples` codes.
+fn last_code(samples: u64) -> u64 {
+    samples.saturating_sub(1)
+}
+
+/// The mapped value scaled to the code range and clamped into it.
+fn scaled(value: f64, fit: &dyn FitFn, samples: u64) -> f64 {
+    let mut mapped_value = fit.function(value);
+    if !mapped_value.is_finite() {
+        mapped_value = 0.;
+    }
+    (mapped_value * samples as f64).clamp(0., samples as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SimpleFitFn;
+
+    #[test]
+    fn modes() {
+        let square = SimpleFitFn {
+            function: |x: f64| x.sqrt(),
+            inverse: |x: f64| x * x,
+            name: "square",
+        };
+        let noise = |value: f64| move || value;
+        let floor = Quantizer::default();
+        assert_eq!(
+            floor.encode(0.5f64.powi(2), &square, 4, (0, 0), &mut noise(0.)),
+            2
+        );
+        assert_eq!(
+            floor.encode(0.7f64.powi(2), &square, 4, (0, 0), &mut noise(0.)),
+            2
+        );
+
+        let nearest = Quantizer::new(Rounding::Nearest, Reconstruction::LowerEdge);
+        assert_eq!(
+            nearest.encode(0.7f64.powi(2), &square, 4, (0, 0), &mut noise(0.)),
+            3
+        );
+
+        let stochastic = Quantizer::new(Rounding::Stochastic, Reconstruction::LowerEdge);
+        let outcomes: Vec<_> = stochastic.outcomes(0.6f64.powi(2), &square, 4).collect();
+        let mean: f64 = outcomes.iter().map(|&(code, p)| code as f64 * p).sum();
+        assert!((mean - 2.4).abs() < 1e-9, "{:?}", outcomes);
+        let encode = |noise_value| {
+            stochastic.encode(0.6f64.powi(2), &square, 4, (0, 0), &mut noise(noise_value))
+        };
+        assert_eq!(encode(0.5), 2);
+        assert_eq!(encode(0.7), 3);
+
+        let decode =
+            |reconstruction| Quantizer::new(Rounding::Floor, reconstruction).decode(1, &square, 4);
+        assert_eq!(decode(Reconstruction::LowerEdge), 0.0625);
+        assert_eq!(decode(Reconstruction::EncodedMidpoint), 0.140625);
+        assert_eq!(decode(Reconstruction::InputMidpoint), 0.15625);
+    }
+
+    #[test]
+    fn dither() {
+        let mut pattern: Vec<_> = (0..64).map(|i| bayer(i % 8, i / 8)).collect();
+        pattern.sort_unstable();
+        assert_eq!(pattern, (0..64).collect::<Vec<_>>());
+
+        let identity = SimpleFitFn {
+            function: |x: f64| x,
+            inverse: |x: f64| x,
+            name: "identity",
+        };
+        // without clamping every dither keeps the expected code unbiased
+        for dither in [
+            Dither::Uniform,
+            Dither::Tpdf,
+            Dither::LowDiscrepancy { seed: 3 },
+            Dither::Ordered,
+        ] {
+            let quantizer =
+                Quantizer::new(Rounding::Nearest, Reconstruction::LowerEdge).with_dither(dither);
+            let outcomes: Vec<_> = quantizer.outcomes(0.425, &identity, 10).collect();
+            let total: f64 = outcomes.iter().map(|&(_, p)| p).sum();
+            let mean: f64 = outcomes.iter().map(|&(code, p)| code as f64 * p).sum();
+            assert!((total - 1.).abs() < 1e-9, "{:?}: {:?}", dither, outcomes);
+            assert!((mean - 4.25).abs() < 1e-9, "{:?}: {:?}", dither, outcomes);
+        }
+    }
+
+    #[test]
+    fn code_range() {
+        let identity = SimpleFitFn {
+            function: |x: f64| x,
+            inverse: |x: f64| x,
+            name: "identity",
+        };
+        for rounding in [Rounding::Floor, Rounding::Nearest, Rounding::Stochastic] {
+            for dither in [Dither::None, Dither::Tpdf, Dither::Ordered] {
+                let quantizer =
+                    Quantizer::new(rounding, Reconstruction::LowerEdge).with_dither(dither);
+                let mut codes = std::collections::BTreeSet::new();
+                for i in 0..=100 {
+                    let value = i as f64 / 100.;
+                    for noise in [0., 0.5, 0.999] {
+                        codes.insert(quantizer.encode(value, &identity, 4, (i, 0), &mut || noise));
+                    }
+                    codes.extend(
+                        quantizer
+                            .outcomes(value, &identity, 4)
+                            .map(|(code, _)| code),
+                    );
+                }
+                assert_eq!(
+                    codes.into_iter().collect::<Vec<_>>(),
+                    [0, 1, 2, 3],
+                    "{:?} {:?}",
+                    rounding,
+                    dither
+                );
+            }
+        }
+    }
+}
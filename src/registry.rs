@@ -0,0 +1,497 @@
+//! Named registry of the models available for fitting.
+//!
+//! The built-in models are registered on first use. Other crates can add their
+//! own [`CreateFitFn`] types with [`register`], after which they are picked up
+//! by [`crate::fit_function`], the error sweeps and model loading by name.
+
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Instant;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::empirical::EmpiricalCdf;
+use crate::lloyd_max::Codebook;
+use crate::metric::ErrorOptions;
+use crate::models::{
+    self, FitConfig, FitReport, OptimizedExp, OptimizedLin, OptimizedLog, OptimizedPow,
+    OptimizedSpline,
+};
+use crate::{distribution_error_with, CreateFitFn, Dist, FitFn};
+
+type FitConstructor =
+    dyn Fn(Dist, u64, &FitConfig) -> Result<(Box<dyn FitFn>, FitReport)> + Send + Sync;
+type ParameterConstructor = dyn Fn(Vec<f64>) -> Result<Box<dyn FitFn>> + Send + Sync;
+type ErrorFunction = dyn Fn(&[(f64, f64)], &dyn FitFn, u64, &ErrorOptions) -> f64 + Send + Sync;
+
+#[derive(Clone)]
+pub struct ModelDescriptor {
+    pub name: String,
+    pub description: String,
+    /// Number of parameters, `None` if it depends on the fitted data.
+    pub parameter_count: Option<usize>,
+    /// Starting simplex of the optimizer, empty for models which are not
+    /// fitted by optimization.
+    pub initial_simplex: Vec<Vec<f64>>,
+    fit: Arc<FitConstructor>,
+    from_parameters: Arc<ParameterConstructor>,
+    error: Arc<ErrorFunction>,
+}
+
+impl ModelDescriptor {
+    /// Describes a model whose parameters are found by optimizing the
+    /// quantization error starting from `initial_simplex`.
+    pub fn parametric<T: CreateFitFn + 'static>(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        initial_simplex: Vec<Vec<f64>>,
+    ) -> Self {
+        let parameter_count = initial_simplex.first().map_or(0, Vec::len);
+        let simplex = initial_simplex.clone();
+        Self {
+            name: name.into(),
+            description: description.into(),
+            parameter_count: Some(parameter_count),
+            initial_simplex,
+            fit: Arc::new(move |dist, levels, config| {
+                let (fit, report) = models::fit::<T>(dist, levels, simplex.clone(), config)?;
+                Ok((Box::new(fit), report))
+            }),
+            from_parameters: Arc::new(|parameters| {
+                check_bounds::<T>(&parameters)?;
+                Ok(Box::new(T::new(parameters)))
+            }),
+            error: Arc::new(default_error),
+        }
+    }
+
+    /// Describes a model with its own fitting procedure.
+    pub fn custom(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        fit: impl Fn(Dist, u64, &FitConfig) -> Result<(Box<dyn FitFn>, FitReport)>
+            + Send
+            + Sync
+            + 'static,
+        from_parameters: impl Fn(Vec<f64>) -> Result<Box<dyn FitFn>> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            parameter_count: None,
+            initial_simplex: Vec::new(),
+            fit: Arc::new(fit),
+            from_parameters: Arc::new(from_parameters),
+            error: Arc::new(default_error),
+        }
+    }
+
+    /// Scores fits of this model with `error` instead of
+    /// [`crate::distribution_error_with`], for models which are quantizers on
+    /// their own rather than curves for [`crate::quantize::Quantizer`].
+    pub fn with_error(
+        mut self,
+        error: impl Fn(&[(f64, f64)], &dyn FitFn, u64, &ErrorOptions) -> f64 + Send + Sync + 'static,
+    ) -> Self {
+        self.error = Arc::new(error);
+        self
+    }
+
+    /// The same model under another name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn fit(
+        &self,
+        dist: Dist,
+        levels: u64,
+        config: &FitConfig,
+    ) -> Result<(Box<dyn FitFn>, FitReport)> {
+        (self.fit)(dist, levels, config)
+    }
+
+    /// Error of `fit`, a fit of this model with `levels` levels, on the
+    /// normalized distribution `dist`.
+    pub fn error(
+        &self,
+        dist: &[(f64, f64)],
+        fit: &dyn FitFn,
+        levels: u64,
+        options: &ErrorOptions,
+    ) -> f64 {
+        (self.error)(dist, fit, levels, options)
+    }
+
+    /// Whether the fitted parameters can be used as the starting point of
+    /// another fit, see [`FitConfig::initial`].
+    pub fn supports_warm_start(&self) -> bool {
+        !self.initial_simplex.is_empty()
+    }
+
+    pub fn from_parameters(&self, parameters: Vec<f64>) -> Result<Box<dyn FitFn>> {
+        if let Some(count) = self.parameter_count {
+            ensure!(
+                parameters.len() == count,
+                "{} expects {} parameters, got {}",
+                self.name,
+                count,
+                parameters.len()
+            );
+        }
+        (self.from_parameters)(parameters)
+    }
+}
+
+impl std::fmt::Debug for ModelDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModelDescriptor")
+            .field("name", &self.name)
+            .field("description", &self.description)
+            .field("parameter_count", &self.parameter_count)
+            .finish()
+    }
+}
+
+fn default_error(dist: &[(f64, f64)], fit: &dyn FitFn, levels: u64, options: &ErrorOptions) -> f64 {
+    distribution_error_with(dist, fit, levels, options)
+}
+
+fn check_bounds<T: CreateFitFn>(parameters: &[f64]) -> Result<()> {
+    for (i, &parameter) in parameters.iter().enumerate() {
+        let bound = T::bound(i);
+        ensure!(
+            bound.contains(parameter),
+            "parameter {} = {} is outside of [{}, {}]",
+            i,
+            parameter,
+            bound.lower,
+            bound.upper
+        );
+    }
+    Ok(())
+}
+
+fn builtin() -> Vec<ModelDescriptor> {
+    vec![
+        ModelDescriptor::parametric::<OptimizedLin>(
+            "linear",
+            "a * x + b",
+            OptimizedLin::initial_simplex(),
+        ),
+        ModelDescriptor::parametric::<OptimizedLog>(
+            "log",
+            "ln((x + a) * d) * b + c",
+            OptimizedLog::initial_simplex(),
+        ),
+        ModelDescriptor::parametric::<OptimizedPow>(
+            "powf",
+            "((x - a) * d)^e * b + c",
+            OptimizedPow::initial_simplex(),
+        ),
+        ModelDescriptor::parametric::<OptimizedExp>(
+            "exp",
+            "exp(x * a) * b + c",
+            OptimizedExp::initial_simplex(),
+        ),
+        spline(OptimizedSpline::DEFAULT_KNOTS).with_name("spline"),
+        empirical(1).with_name("empirical"),
+        ModelDescriptor::custom(
+            "lloyd-max",
+            "optimal scalar quantizer for the mean squared error",
+            |dist, levels, config| {
+                let start = Instant::now();
+                let fit = Codebook::lloyd_max(&dist, levels);
+                let error = fit.distribution_error(&dist, &config.error);
+                let report = FitReport::from_error(error, config.error.metric, start);
+                Ok((Box::new(fit), report))
+            },
+            |parameters| {
+                ensure!(
+                    parameters.len() % 2 == 1,
+                    "lloyd-max expects an odd number of parameters"
+                );
+                Ok(Box::new(<Codebook as CreateFitFn>::new(parameters)))
+            },
+        )
+        .with_error(|dist, fit, _, options| {
+            <Codebook as CreateFitFn>::new(fit.parameters().to_vec())
+                .distribution_error(dist, options)
+        }),
+    ]
+}
+
+/// Spline with `knots` knots, named `spline-<knots>`. Panics for fewer than
+/// two knots.
+///
+/// [`descriptor`] also resolves these names if they were not registered.
+pub fn spline(knots: usize) -> ModelDescriptor {
+    ModelDescriptor {
+        parameter_count: None,
+        from_parameters: Arc::new(|parameters| {
+            ensure!(parameters.len() >= 2, "spline expects at least two knots");
+            check_bounds::<OptimizedSpline>(&parameters)?;
+            Ok(Box::new(<OptimizedSpline as CreateFitFn>::new(parameters)))
+        }),
+        ..ModelDescriptor::parametric::<OptimizedSpline>(
+            format!("spline-{}", knots),
+            format!(
+                "monotone piecewise linear curve through {} equally spaced knots",
+                knots
+            ),
+            OptimizedSpline::initial_simplex(knots),
+        )
+    }
+}
+
+/// Empirical cdf smoothed with a moving average of `window` points, named
+/// `empirical-<window>`, see [`EmpiricalCdf::smoothed`].
+///
+/// [`descriptor`] also resolves these names if they were not registered.
+/// Panics if `window` is even.
+pub fn empirical(window: usize) -> ModelDescriptor {
+    ModelDescriptor::custom(
+        format!("empirical-{}", window),
+        "lookup table of the empirical cdf (histogram equalization)",
+        move |dist, levels, config| {
+            let start = Instant::now();
+            let fit = EmpiricalCdf::smoothed(&dist, window);
+            let report = FitReport::direct(&fit, &dist, levels, &config.error, start);
+            Ok((Box::new(fit), report))
+        },
+        |parameters| {
+            ensure!(
+                !parameters.is_empty() && parameters.len().is_multiple_of(2),
+                "empirical expects an even number of parameters"
+            );
+            Ok(Box::new(<EmpiricalCdf as CreateFitFn>::new(parameters)))
+        },
+    )
+}
+
+/// Descriptors of models whose name carries their configuration, e.g.
+/// `spline-12` or `empirical-5`.
+fn parameterized(name: &str) -> Option<Result<ModelDescriptor>> {
+    let (kind, value) = name.rsplit_once('-')?;
+    let value: usize = value.parse().ok()?;
+    match kind {
+        "spline" => Some(if value >= 2 {
+            Ok(spline(value))
+        } else {
+            Err(anyhow::anyhow!("a spline needs at least two knots"))
+        }),
+        "empirical" => Some(if value % 2 == 1 {
+            Ok(empirical(value))
+        } else {
+            Err(anyhow::anyhow!("the smoothing window has to be odd"))
+        }),
+        _ => None,
+    }
+}
+
+/// A set of named models. The free functions of this module work on a global
+/// registry holding the built-in models; a separate `Registry` keeps custom
+/// models out of it, e.g. in tests.
+#[derive(Clone)]
+pub struct Registry {
+    models: Vec<ModelDescriptor>,
+}
+
+impl Default for Registry {
+    /// A registry of the built-in models.
+    fn default() -> Self {
+        Registry { models: builtin() }
+    }
+}
+
+impl Registry {
+    /// Adds a model, replacing any model with the same name.
+    pub fn register(&mut self, descriptor: ModelDescriptor) {
+        match self
+            .models
+            .iter_mut()
+            .find(|model| model.name == descriptor.name)
+        {
+            Some(model) => *model = descriptor,
+            None => self.models.push(descriptor),
+        }
+    }
+
+    /// All models in registration order.
+    pub fn descriptors(&self) -> &[ModelDescriptor] {
+        &self.models
+    }
+
+    /// The model `name`, or a configured built-in model such as `spline-12`
+    /// (see [`spline`]) or `empirical-5` (see [`empirical`]).
+    pub fn descriptor(&self, name: &str) -> Result<ModelDescriptor> {
+        match self.models.iter().find(|model| model.name == name) {
+            Some(model) => Ok(model.clone()),
+            None => {
+                parameterized(name).with_context(|| format!("Unknown fit function {:?}", name))?
+            }
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.models.iter().map(|model| model.name.clone()).collect()
+    }
+
+    /// Reconstructs a model of the kind `name` from its parameters.
+    pub fn from_parameters(&self, name: &str, parameters: Vec<f64>) -> Result<Box<dyn FitFn>> {
+        self.descriptor(name)?.from_parameters(parameters)
+    }
+}
+
+fn registry() -> &'static RwLock<Registry> {
+    static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(Registry::default()))
+}
+
+/// Adds a model to the global registry, replacing any model with the same name.
+pub fn register(descriptor: ModelDescriptor) {
+    registry().write().unwrap().register(descriptor)
+}
+
+/// All registered models in registration order.
+pub fn descriptors() -> Vec<ModelDescriptor> {
+    registry().read().unwrap().descriptors().to_vec()
+}
+
+/// The registered model `name`, see [`Registry::descriptor`].
+pub fn descriptor(name: &str) -> Result<ModelDescriptor> {
+    registry().read().unwrap().descriptor(name)
+}
+
+pub fn names() -> Vec<String> {
+    registry().read().unwrap().names()
+}
+
+/// Reconstructs a model of the registered kind `name` from its parameters.
+pub fn from_parameters(name: &str, parameters: Vec<f64>) -> Result<Box<dyn FitFn>> {
+    descriptor(name)?.from_parameters(parameters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_splines() {
+        let spline = descriptor("spline-4").unwrap();
+        assert_eq!(spline.name, "spline-4");
+        assert_eq!(spline.initial_simplex[0].len(), 4);
+        assert_eq!(descriptor("spline").unwrap().initial_simplex[0].len(), 8);
+        assert!(descriptor("spline-1").is_err());
+        assert!(descriptor("spline-four").is_err());
+        assert!(descriptor("linear-4").is_err());
+    }
+
+    #[test]
+    fn lookup_and_register() {
+        let names = names();
+        for name in [
+            "linear",
+            "log",
+            "powf",
+            "exp",
+            "spline",
+            "empirical",
+            "lloyd-max",
+        ] {
+            assert!(
+                names.iter().any(|n| n == name),
+                "{} is not registered",
+                name
+            );
+            assert_eq!(descriptor(name).unwrap().name, name);
+        }
+        assert!(descriptor("pow").is_err());
+
+        let mut registry = Registry::default();
+        registry.register(ModelDescriptor::parametric::<OptimizedLin>(
+            "test-linear",
+            "a * x + b, registered by a test",
+            vec![vec![2., 0.], vec![2.1, 0.], vec![2., 0.1]],
+        ));
+        assert_eq!(registry.names().len(), names.len() + 1);
+        let custom = registry.descriptor("test-linear").unwrap();
+        assert_eq!(custom.parameter_count, Some(2));
+        let fit = registry
+            .from_parameters("test-linear", vec![0.5, 0.25])
+            .unwrap();
+        assert_eq!(fit.function(1.), 0.75);
+        assert!(registry.from_parameters("test-linear", vec![0.5]).is_err());
+        assert!(descriptor("test-linear").is_err());
+    }
+
+    #[test]
+    fn parameter_round_trip() {
+        let parameters = [
+            ("linear", vec![1.5, -0.1]),
+            ("log", vec![0.01, 0.3, 0.5, 4.]),
+            ("powf", vec![0.02, 1., 0., 1., 0.45]),
+            ("exp", vec![1., 1., 0.]),
+            ("spline", vec![0., 0.5, 0.25, 0.25]),
+            ("empirical", vec![0., 0.5, 1., 0., 0.8, 1.]),
+            ("lloyd-max", vec![0.25, 0.75, 0., 0.5, 1.]),
+        ];
+        for (name, parameters) in parameters {
+            let fit = from_parameters(name, parameters.clone()).unwrap();
+            assert_eq!(fit.name(), name);
+            assert_eq!(fit.parameters(), parameters.as_slice());
+            let restored = from_parameters(fit.name(), fit.parameters().to_vec()).unwrap();
+            for x in [0.1, 0.5, 0.9] {
+                assert_eq!(restored.function(x), fit.function(x), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn smoothed_empirical() {
+        let dist: Vec<_> = (1..=20)
+            .map(|i| (i as f64 / 20., (i as f64 / 20.).powi(2)))
+            .collect();
+        let config = FitConfig::default();
+        let (raw, _) = descriptor("empirical")
+            .unwrap()
+            .fit(dist.clone(), 4, &config)
+            .unwrap();
+        let (smoothed, _) = descriptor("empirical-5")
+            .unwrap()
+            .fit(dist.clone(), 4, &config)
+            .unwrap();
+        assert_eq!(raw.parameters(), EmpiricalCdf::new(&dist).parameters());
+        assert_eq!(
+            smoothed.parameters(),
+            EmpiricalCdf::smoothed(&dist, 5).parameters()
+        );
+        assert_ne!(raw.parameters(), smoothed.parameters());
+        assert!(descriptor("empirical-4").is_err());
+    }
+
+    #[test]
+    fn codebook_error() {
+        use crate::quantize::{Quantizer, Reconstruction, Rounding};
+
+        let dist: Vec<_> = (1..=50)
+            .map(|i| ((i as f64 / 50.).powi(3), i as f64 / 50.))
+            .collect();
+        let options = ErrorOptions::default().with_quantizer(Quantizer::new(
+            Rounding::Nearest,
+            Reconstruction::InputMidpoint,
+        ));
+        let config = FitConfig::default().with_error(options.clone());
+        let model = descriptor("lloyd-max").unwrap();
+        let (fit, report) = model.fit(dist.clone(), 4, &config).unwrap();
+        let codebook = Codebook::lloyd_max(&dist, 4);
+        let error = model.error(&dist, fit.as_ref(), 4, &options);
+        assert_eq!(error, codebook.distribution_error(&dist, &options));
+        assert_eq!(report.best_cost, error);
+        // the default error function would measure a different quantizer
+        assert_ne!(
+            error,
+            distribution_error_with(&dist, fit.as_ref(), 4, &options)
+        );
+    }
+}
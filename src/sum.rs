@@ -8,7 +8,7 @@ impl Sum {
         Sum { partials: vec![] }
     }
 
-    fn add(&mut self, mut x: f64) {
+    pub fn add(&mut self, mut x: f64) {
         let mut j = 0;
         // This inner loop applies `hi`/`lo` summation to each
         // partial so that the list of partial sums remains exact.
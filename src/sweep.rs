@@ -0,0 +1,214 @@
+//! Fitting a model for a range of bit depths.
+
+use std::ops::Range;
+
+use anyhow::Result;
+use rayon::prelude::*;
+
+use crate::models::FitConfig;
+use crate::registry::ModelDescriptor;
+use crate::FitFn;
+
+/// `(levels, error)` pairs of a [`Sweep`], in order of increasing level count.
+pub type SweepErrors = Vec<(u64, f64)>;
+
+/// The level counts a model is fitted for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sweep {
+    /// Number of quantization levels of every depth, in increasing order.
+    pub levels: Vec<u64>,
+    /// Start each depth from the best parameters of the previous one instead
+    /// of the initial simplex of the model. Depths are fitted sequentially
+    /// in that case, otherwise in parallel.
+    pub warm_start: bool,
+}
+
+impl Default for Sweep {
+    fn default() -> Self {
+        Self::new(0..12)
+    }
+}
+
+impl Sweep {
+    /// Whole bit depths, quantizing to `1 << bits` levels.
+    pub fn new(bits: Range<u32>) -> Self {
+        assert!(bits.end <= 63, "can not quantize to more than 63 bits");
+        Self::from_levels(bits.map(|bits| 1 << bits).collect())
+    }
+
+    /// Arbitrary level counts, e.g. 3, 5, 6 or 10 levels. Their errors are
+    /// allocated with [`crate::packing::allocate_levels`] through
+    /// [`crate::packing::LevelErrors::from_sweep`], bit allocation only
+    /// accepts sweeps of [`Sweep::new`].
+    pub fn from_levels(mut levels: Vec<u64>) -> Self {
+        assert!(
+            levels.iter().all(|&l| l > 0),
+            "level counts have to be positive"
+        );
+        levels.sort_unstable();
+        levels.dedup();
+        Self {
+            levels,
+            warm_start: false,
+        }
+    }
+
+    /// Fractional bit depths, quantizing to `round(2^bits)` levels. Depths
+    /// which round to the same level count are only fitted once.
+    pub fn from_bits(bits: impl IntoIterator<Item = f64>) -> Self {
+        Self::from_levels(
+            bits.into_iter()
+                .map(|bits| 2f64.powf(bits).round().max(1.) as u64)
+                .collect(),
+        )
+    }
+
+    pub fn with_warm_start(mut self, warm_start: bool) -> Self {
+        self.warm_start = warm_start;
+        self
+    }
+
+    pub fn levels(&self) -> impl Iterator<Item = u64> + '_ {
+        self.levels.iter().copied()
+    }
+
+    /// The bit depth of every level count, `log2(levels)`.
+    pub fn bits(&self) -> impl Iterator<Item = f64> + '_ {
+        self.levels().map(|levels| (levels as f64).log2())
+    }
+
+    /// Fits `model` to `dist` for every depth and maps each fit with
+    /// `evaluate`, returning the results in order of increasing depth.
+    pub fn map<R: Send>(
+        &self,
+        model: &ModelDescriptor,
+        dist: &[(f64, f64)],
+        config: &FitConfig,
+        evaluate: impl Fn(u64, &dyn FitFn) -> R + Sync,
+    ) -> Result<Vec<R>> {
+        let fit = |levels: u64, config: &FitConfig| -> Result<(R, Vec<f64>)> {
+            let (fit, report) = model.fit(dist.to_vec(), levels, config)?;
+            log::debug!("fitted {} with {} levels: {:?}", model.name, levels, report);
+            Ok((evaluate(levels, fit.as_ref()), fit.parameters().to_vec()))
+        };
+        if !(self.warm_start && model.supports_warm_start()) {
+            let levels: Vec<_> = self.levels().collect();
+            return levels
+                .par_iter()
+                .map(|&levels| Ok(fit(levels, config)?.0))
+                .collect();
+        }
+        let mut config = config.clone();
+        let mut results = Vec::with_capacity(self.levels.len());
+        for levels in self.levels() {
+            let (result, parameters) = fit(levels, &config)?;
+            if !parameters.is_empty() {
+                config.initial = Some(parameters);
+            }
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    /// Fits `model` to `train` for every depth and returns the level count
+    /// and the error on `test` of every depth.
+    pub fn errors(
+        &self,
+        model: &ModelDescriptor,
+        train: &[(f64, f64)],
+        test: &[(f64, f64)],
+        config: &FitConfig,
+    ) -> Result<SweepErrors> {
+        self.map(model, train, config, |levels, fit| {
+            (levels, model.error(test, fit, levels, &config.error))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packing::DynamicErrorFunction;
+    use crate::registry;
+    use std::sync::{Arc, Mutex};
+
+    fn dist() -> Vec<(f64, f64)> {
+        (1..=200)
+            .map(|i| (i as f64 / 200., (i as f64 / 200.).sqrt()))
+            .collect()
+    }
+
+    #[test]
+    fn level_counts() {
+        let dist = dist();
+        let config = FitConfig::default().with_max_iters(100);
+        let model = registry::descriptor("linear").unwrap();
+        let sweep = Sweep::from_levels(vec![10, 3, 5, 3]);
+        let errors = sweep.errors(&model, &dist, &dist, &config).unwrap();
+        let levels: Vec<_> = errors.iter().map(|&(levels, _)| levels).collect();
+        assert_eq!(levels, vec![3, 5, 10]);
+        assert!(errors.windows(2).all(|w| w[0].1 > w[1].1), "{:?}", errors);
+        // bit allocation only accepts whole bits starting at zero bits
+        assert!(DynamicErrorFunction::from_sweep(&errors).is_err());
+
+        let bits = Sweep::new(0..4)
+            .errors(&model, &dist, &dist, &config)
+            .unwrap();
+        assert_eq!(
+            bits.iter().map(|e| e.0).collect::<Vec<_>>(),
+            vec![1, 2, 4, 8]
+        );
+        assert_eq!(DynamicErrorFunction::from_sweep(&bits).unwrap().len(), 4);
+        assert!(DynamicErrorFunction::from_sweep(&bits[1..]).is_err());
+    }
+
+    #[test]
+    fn warm_start() {
+        let dist = dist();
+        let config = FitConfig::default().with_max_iters(200);
+        let model = registry::descriptor("powf").unwrap();
+        let sweep = Sweep::new(2..6);
+        let cold = sweep.errors(&model, &dist, &dist, &config).unwrap();
+        let warm = sweep
+            .clone()
+            .with_warm_start(true)
+            .errors(&model, &dist, &dist, &config)
+            .unwrap();
+        assert_eq!(
+            warm.iter().map(|e| e.0).collect::<Vec<_>>(),
+            cold.iter().map(|e| e.0).collect::<Vec<_>>()
+        );
+        assert!(warm.iter().all(|e| e.1.is_finite()), "{:?}", warm);
+        // only later depths start from previous parameters
+        assert_eq!(warm[0], cold[0]);
+
+        // each depth starts from the parameters of the previous one
+        let fits = Arc::new(Mutex::new(Vec::new()));
+        let recorded = fits.clone();
+        let mut recording = ModelDescriptor::custom(
+            "recording-powf",
+            "powf which records where each fit started",
+            move |dist, levels, config| {
+                let (fit, report) = model.fit(dist, levels, config)?;
+                let start = config.initial.clone();
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((start, fit.parameters().to_vec()));
+                Ok((fit, report))
+            },
+            |_| unreachable!(),
+        );
+        recording.initial_simplex = registry::descriptor("powf").unwrap().initial_simplex;
+        sweep
+            .with_warm_start(true)
+            .map(&recording, &dist, &config, |_, _| ())
+            .unwrap();
+        let fits = fits.lock().unwrap();
+        assert_eq!(fits.len(), 4);
+        assert_eq!(fits[0].0, None);
+        for pair in fits.windows(2) {
+            assert_eq!(pair[1].0.as_ref(), Some(&pair[0].1));
+        }
+    }
+}
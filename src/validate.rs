@@ -0,0 +1,106 @@
+//! Sanity checks for fitted transfer functions.
+
+use crate::FitFn;
+
+pub const DEFAULT_SAMPLES: usize = 1024;
+
+/// Result of checking a [`FitFn`] on equally spaced points of [0, 1].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationReport {
+    pub samples: usize,
+    /// Input intervals on which the function decreases.
+    pub monotonicity_violations: Vec<(f64, f64)>,
+    /// Smallest and largest finite output.
+    pub output_range: (f64, f64),
+    /// Largest `|inverse(function(x)) - x|`.
+    pub max_round_trip_error: f64,
+    /// Inputs for which the function or the round trip is not finite.
+    pub non_finite: Vec<f64>,
+}
+
+impl ValidationReport {
+    pub fn is_monotone(&self) -> bool {
+        self.monotonicity_violations.is_empty()
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.non_finite.is_empty()
+    }
+
+    /// Whether every output lies in [0, 1], so no input gets clamped by
+    /// [`crate::encode`].
+    pub fn maps_into_unit_interval(&self) -> bool {
+        self.output_range.0 >= 0. && self.output_range.1 <= 1.
+    }
+
+    /// Whether the function is finite, monotone and can be inverted within
+    /// `tolerance`.
+    pub fn is_valid(&self, tolerance: f64) -> bool {
+        self.is_finite() && self.is_monotone() && self.max_round_trip_error <= tolerance
+    }
+}
+
+pub fn validate(fit: &dyn FitFn) -> ValidationReport {
+    validate_with(fit, DEFAULT_SAMPLES)
+}
+
+pub fn validate_with(fit: &dyn FitFn, samples: usize) -> ValidationReport {
+    let samples = samples.max(2);
+    let mut report = ValidationReport {
+        samples,
+        monotonicity_violations: Vec::new(),
+        output_range: (f64::INFINITY, f64::NEG_INFINITY),
+        max_round_trip_error: 0.,
+        non_finite: Vec::new(),
+    };
+    let mut last: Option<(f64, f64)> = None;
+    for i in 0..samples {
+        let x = i as f64 / (samples - 1) as f64;
+        let y = fit.function(x);
+        let round_trip = fit.inverse(y);
+        if !y.is_finite() || !round_trip.is_finite() {
+            report.non_finite.push(x);
+            last = None;
+            continue;
+        }
+        report.output_range.0 = report.output_range.0.min(y);
+        report.output_range.1 = report.output_range.1.max(y);
+        report.max_round_trip_error = report.max_round_trip_error.max((round_trip - x).abs());
+
+        if let Some((last_x, last_y)) = last {
+            if y < last_y {
+                match report.monotonicity_violations.last_mut() {
+                    Some(violation) if violation.1 == last_x => violation.1 = x,
+                    _ => report.monotonicity_violations.push((last_x, x)),
+                }
+            }
+        }
+        last = Some((x, y));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SimpleFitFn;
+
+    #[test]
+    fn detects_violations() {
+        let valid = SimpleFitFn {
+            function: |x: f64| x.sqrt(),
+            inverse: |x: f64| x * x,
+            name: "sqrt",
+        };
+        assert!(validate(&valid).is_valid(1e-9));
+
+        let decreasing = SimpleFitFn {
+            function: |x: f64| (x - 0.5).abs(),
+            inverse: |x: f64| x + 0.5,
+            name: "abs",
+        };
+        let report = validate_with(&decreasing, 11);
+        assert_eq!(report.monotonicity_violations, vec![(0., 0.5)]);
+        assert!(!report.is_valid(1e-9));
+    }
+}